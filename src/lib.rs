@@ -1,249 +1,8525 @@
-use csv::Reader;
+use csv::ReaderBuilder;
 use csv::Writer;
+use csv::WriterBuilder;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rusqlite::{params, Connection, OpenFlags};
+use serde_json::json;
 use std::cmp;
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::env;
 use std::error::Error;
 use std::f64;
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::Write;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const INITIAL_RATING: f64 = 1000.;
 // RATING_CONST determines how a expected_win_probability is inferred from a difference in rating.
 // Set to 200/ln(3) such that a rating difference of 200 gives a 75/25 expected win probability.
+// It can be overridden with --scale; 400/ln(10) = 173.7178 reproduces the standard FIDE-style
+// Elo scale, on which a 400 point difference gives 10/1 odds.
 const RATING_CONST: f64 = 182.047845;
 const K: f64 = 40.; // Rating sensitivity (max. rating change from a single game or twice the rating change from an evenly matched game).
 
-enum MatchResult {
+// Players who haven't played for DECAY_INACTIVE_DAYS lose DECAY_RATE of the amount by which their
+// rating exceeds INITIAL_RATING each time `leelo decay` is run.
+const DECAY_INACTIVE_DAYS: u64 = 90;
+const DECAY_RATE: f64 = 0.05;
+
+// Glicko mode tracks a rating deviation (RD) per player, the uncertainty in their rating. New
+// players start at GLICKO_INITIAL_RD, which shrinks with every game they play. Glicko ratings use the
+// standard Glicko scale rather than RATING_CONST.
+const GLICKO_INITIAL_RD: f64 = 350.;
+const GLICKO_Q: f64 = f64::consts::LN_10 / 400.;
+
+// A game won by a margin m is rated with K * (1 + MARGIN_SCALE * ln(1 + m)), so a margin of 0
+// rates exactly like a game without one and larger margins count for progressively less extra.
+const MARGIN_SCALE: f64 = 0.5;
+
+// With --upset-bonus, a win by the lower-rated player, rated `gap` points below their opponent,
+// is rated with K * (1 + min(gap, UPSET_GAP) / UPSET_GAP). The bonus grows linearly with the gap
+// and tops out at double K once the gap reaches UPSET_GAP. Both players' K is scaled, so the
+// favorite loses more as well. This is on top of the expected score, which already rewards an
+// upset, so it deliberately departs from standard Elo.
+const UPSET_GAP: f64 = 400.;
+
+// Players with fewer than PROVISIONAL_GAMES games are rated with PROVISIONAL_K instead of K, so
+// that their rating moves quickly towards their true strength.
+const PROVISIONAL_GAMES: u32 = 20;
+const PROVISIONAL_K: f64 = 80.;
+
+// `leelo view` marks the ratings of players with fewer than UNCERTAIN_GAMES games with a `?`, as
+// too few games have been played for the rating to be reliable.
+const UNCERTAIN_GAMES: u32 = 10;
+
+// The class labels shown by `view --classes`, as (lowest rating, label) from the top down. A
+// player gets the first class whose lowest rating their combined rating reaches. These are the
+// USCF classes, except that J, officially 100 to 199, takes every rating below 200 so that
+// everyone has a class.
+const CLASSES: [(f64, &str); 13] = [
+    (2400., "Senior Master"),
+    (2200., "Master"),
+    (2000., "Expert"),
+    (1800., "A"),
+    (1600., "B"),
+    (1400., "C"),
+    (1200., "D"),
+    (1000., "E"),
+    (800., "F"),
+    (600., "G"),
+    (400., "H"),
+    (200., "I"),
+    (f64::NEG_INFINITY, "J"),
+];
+
+// Players are rated separately for games played with White and with Black.
+#[derive(Clone)]
+struct PlayerRecord {
+    white_rating: f64,
+    black_rating: f64,
+    rd: f64,
+    games: u32,
+    wins: u32,
+    losses: u32,
+    draws: u32,
+    // When the player's last rated game was recorded, as an ISO-8601 timestamp. None for
+    // players who haven't played since tables started keeping it.
+    last_played: Option<String>,
+}
+
+impl PlayerRecord {
+    fn new(rating: f64) -> PlayerRecord {
+        PlayerRecord {
+            white_rating: rating,
+            black_rating: rating,
+            rd: GLICKO_INITIAL_RD,
+            games: 0,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+            last_played: None,
+        }
+    }
+
+    // Combined rating, the average of the White and Black ratings.
+    fn rating(&self) -> f64 {
+        (self.white_rating + self.black_rating) / 2.
+    }
+
+    // Counts a game in which the player scored `score` (1 for a win, 0 for a loss, 0.5 for a draw).
+    fn add_game(&mut self, score: f64) {
+        self.games += 1;
+        self.last_played = Some(timestamp());
+        match score {
+            s if s > 0.5 => self.wins += 1,
+            s if s < 0.5 => self.losses += 1,
+            _ => self.draws += 1,
+        }
+    }
+
+    fn remove_game(&mut self, score: f64) {
+        self.games = self.games.saturating_sub(1);
+        match score {
+            s if s > 0.5 => self.wins = self.wins.saturating_sub(1),
+            s if s < 0.5 => self.losses = self.losses.saturating_sub(1),
+            _ => self.draws = self.draws.saturating_sub(1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchResult {
     WhiteWin,
     BlackWin,
     Draw,
+    // Any other split of the point, e.g. 0.75-0.25 for an adjourned game, as the (white, black)
+    // scores. They sum to 1.
+    Partial(f64, f64),
+}
+
+impl MatchResult {
+    // Besides the standard notation, results can be given by the winning color or as a draw,
+    // or as any x-y split of the point between 0 and 1.
+    fn parse(score: &str) -> Result<MatchResult, Box<dyn Error>> {
+        match score.to_lowercase().as_str() {
+            "white" => return Ok(MatchResult::WhiteWin),
+            "black" => return Ok(MatchResult::BlackWin),
+            "½-½" | "1/2-1/2" | "draw" | "d" => return Ok(MatchResult::Draw),
+            _ => {}
+        }
+
+        let (white, black) = match score.split_once('-') {
+            Some((white, black)) => match (white.parse::<f64>(), black.parse::<f64>()) {
+                (Ok(white), Ok(black)) => (white, black),
+                _ => return Err("unable to interpret score argument.".into()),
+            },
+            None => return Err("unable to interpret score argument.".into()),
+        };
+        if !((0. ..=1.).contains(&white) && (0. ..=1.).contains(&black)) {
+            return Err(format!("scores must be between 0 and 1, got {}.", score).into());
+        }
+        // Allows for decimal splits such as 0.7-0.3 that don't sum to exactly 1 in binary.
+        if (white + black - 1.).abs() > 1e-9 {
+            return Err(format!("scores must sum to 1, got {}.", score).into());
+        }
+        Ok(match (white, black) {
+            (1., _) => MatchResult::WhiteWin,
+            (0., _) => MatchResult::BlackWin,
+            (0.5, _) => MatchResult::Draw,
+            _ => MatchResult::Partial(white, black),
+        })
+    }
+
+    // The (white, black) scores for this result.
+    fn scores(&self) -> (f64, f64) {
+        match self {
+            MatchResult::WhiteWin => (1., 0.),
+            MatchResult::BlackWin => (0., 1.),
+            MatchResult::Draw => (0.5, 0.5),
+            MatchResult::Partial(white, black) => (*white, *black),
+        }
+    }
+}
+
+impl fmt::Display for MatchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatchResult::WhiteWin => write!(f, "1-0"),
+            MatchResult::BlackWin => write!(f, "0-1"),
+            MatchResult::Draw => write!(f, "0.5-0.5"),
+            MatchResult::Partial(white, black) => write!(f, "{}-{}", white, black),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct HistoryEntry {
+    white_player_id: String,
+    black_player_id: String,
+    result: MatchResult,
+    timestamp: String,
+    // Pre-game ratings for the colors played, stored so the game can be undone exactly. Missing in
+    // older history files.
+    white_rating: Option<f64>,
+    black_rating: Option<f64>,
+    white_rd: Option<f64>,
+    black_rd: Option<f64>,
+    // White's expected score from the pre-game ratings, on the Elo curve even for Glicko games,
+    // for `calibration`. Missing in older history files.
+    expected_score: Option<f64>,
+}
+
+// A manual change to a player's rating outside of a game, such as a forfeit penalty.
+#[derive(Clone)]
+struct Adjustment {
+    player_id: String,
+    delta: f64,
+    timestamp: String,
+    // Pre-adjustment ratings, stored so the adjustment can be undone exactly.
+    white_rating: f64,
+    black_rating: f64,
+}
+
+// A game between two teams.
+#[derive(Clone)]
+struct TeamGame {
+    white_team: Vec<String>,
+    black_team: Vec<String>,
+    result: MatchResult,
+    timestamp: String,
+    // Each player's pre-game rating for their team's color, in team order, stored so the game
+    // can be undone exactly.
+    white_ratings: Vec<f64>,
+    black_ratings: Vec<f64>,
+}
+
+// A free-for-all game, with the players in finishing order.
+#[derive(Clone)]
+struct FreeForAllGame {
+    ranking: Vec<String>,
+    timestamp: String,
+    // Each player's pre-game ratings, in finishing order, stored so the game can be undone
+    // exactly.
+    white_ratings: Vec<f64>,
+    black_ratings: Vec<f64>,
+}
+
+// A run of `decay`, with the players whose ratings it decayed.
+#[derive(Clone)]
+struct Decay {
+    player_ids: Vec<String>,
+    timestamp: String,
+    // Each player's pre-decay ratings, stored so the decay can be undone exactly.
+    white_ratings: Vec<f64>,
+    black_ratings: Vec<f64>,
+}
+
+// A row of the history file. Adjustments are written with the player as White, no Black
+// player and the signed delta in place of the result. Team games are written with
+// comma-separated teams and ratings, and the result prefixed with "team ". Free-for-all games
+// are written with the comma-separated finishing order as White, no Black player and "ffa" as
+// the result, and decays the same way with the decayed players and "decay".
+#[derive(Clone)]
+enum HistoryRecord {
+    Game(HistoryEntry),
+    Adjustment(Adjustment),
+    TeamGame(TeamGame),
+    FreeForAll(FreeForAllGame),
+    Decay(Decay),
+}
+
+// How view-like commands (view, top, list and stats) print their output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Csv,
+    Json,
+    Markdown,
+    // A complete, styled page for publishing.
+    Html,
+}
+
+impl OutputFormat {
+    fn parse(name: &str) -> Result<OutputFormat, Box<dyn Error>> {
+        match name {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
+            _ => Err(format!(
+                "unknown format '{}'. Expected text, csv, json, markdown or html.",
+                name
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SortOrder {
+    RatingDesc,
+    RatingAsc,
+    Name,
+    // Most games played first.
+    Games,
+}
+
+impl SortOrder {
+    fn parse(key: &str) -> Result<SortOrder, Box<dyn Error>> {
+        match key {
+            "rating-desc" => Ok(SortOrder::RatingDesc),
+            "rating-asc" => Ok(SortOrder::RatingAsc),
+            "name" => Ok(SortOrder::Name),
+            "games" => Ok(SortOrder::Games),
+            _ => Err(format!(
+                "unknown sort key '{}'. Expected rating-desc, rating-asc, name or games.",
+                key
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum RatingSystem {
+    #[default]
+    Elo,
+    Glicko,
+}
+
+impl RatingSystem {
+    fn parse(name: &str) -> Result<RatingSystem, Box<dyn Error>> {
+        match name {
+            "elo" => Ok(RatingSystem::Elo),
+            "glicko" => Ok(RatingSystem::Glicko),
+            _ => Err(format!("unknown rating system '{}'. Expected elo or glicko.", name).into()),
+        }
+    }
+}
+
+// Settings that control how a single game changes the players' ratings.
+#[derive(Default)]
+struct RatingOptions {
+    system: RatingSystem,
+    // Overrides the per-player K-factor. Elo only.
+    k: Option<f64>,
+    // Margin of victory (e.g. material up at the end), which scales K. Elo only.
+    margin: Option<f64>,
+    // Bounds that new ratings are clamped to. Elo only. The game is still rated from the players'
+    // true ratings, and clamping is applied after K, so it cuts off a provisional player's larger
+    // swings in the same way as anyone else's.
+    floor: Option<f64>,
+    ceiling: Option<f64>,
+    // Replaces RATING_CONST in the expected score. Elo only.
+    scale: Option<f64>,
+    // A draw between players whose ratings (for the colors they played, or the team averages in a
+    // team game) differ by at most this many points leaves both ratings unchanged. The game still
+    // counts towards games played and the players' records. Elo only.
+    no_draw_change: Option<f64>,
+    // Importance of the game, e.g. 2 for a playoff, which multiplies the rating change. Elo only.
+    weight: Option<f64>,
+    // Largest number of points a single game may move a rating. Each player's change is capped
+    // independently, so with different K-factors the two changes need not stay equal. Applied
+    // before --floor and --ceiling. Elo only.
+    max_change: Option<f64>,
+    // Scales K up when the lower-rated player wins, following UPSET_GAP. Elo only.
+    upset_bonus: bool,
+}
+
+impl RatingOptions {
+    fn scale(&self) -> f64 {
+        self.scale.unwrap_or(RATING_CONST)
+    }
+
+    // The factor applied to every player's K for this game.
+    fn k_multiplier(&self) -> f64 {
+        self.margin.map_or(1., margin_multiplier) * self.weight.unwrap_or(1.)
+    }
+
+    // The extra factor applied to both players' K when the game is an upset.
+    fn upset_multiplier(&self, result: MatchResult, white_rating: f64, black_rating: f64) -> f64 {
+        let (white_score, black_score) = result.scores();
+        let gap = if white_score > black_score {
+            black_rating - white_rating
+        } else if black_score > white_score {
+            white_rating - black_rating
+        } else {
+            0.
+        };
+        if self.upset_bonus && gap > 0. {
+            1. + gap.min(UPSET_GAP) / UPSET_GAP
+        } else {
+            1.
+        }
+    }
+
+    fn skips_draw(&self, result: MatchResult, white_rating: f64, black_rating: f64) -> bool {
+        match self.no_draw_change {
+            Some(threshold) => {
+                result == MatchResult::Draw && (white_rating - black_rating).abs() <= threshold
+            }
+            None => false,
+        }
+    }
+
+    fn cap(&self, before: f64, after: f64) -> f64 {
+        match self.max_change {
+            Some(max_change) => before + (after - before).clamp(-max_change, max_change),
+            None => after,
+        }
+    }
+
+    fn clamp(&self, rating: f64) -> f64 {
+        let rating = self.floor.map_or(rating, |floor| rating.max(floor));
+        self.ceiling.map_or(rating, |ceiling| rating.min(ceiling))
+    }
+}
+
+// How `merge` resolves a player present in both tables.
+#[derive(Clone, Copy)]
+enum ConflictPolicy {
+    KeepA,
+    KeepB,
+    Average,
+}
+
+impl ConflictPolicy {
+    fn parse(name: &str) -> Result<ConflictPolicy, Box<dyn Error>> {
+        match name {
+            "keep-a" => Ok(ConflictPolicy::KeepA),
+            "keep-b" => Ok(ConflictPolicy::KeepB),
+            "average" => Ok(ConflictPolicy::Average),
+            _ => Err(format!(
+                "unknown conflict policy '{}'. Expected keep-a, keep-b or average.",
+                name
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ViewOptions {
+    records: bool,
+    combined: bool,
+    rank: bool,
+    // Decimal places of the printed ratings.
+    precision: usize,
+    // Highlights the top three ranks in text output with ANSI colors.
+    color: bool,
+    // Shows each player's class from CLASSES after the ratings.
+    classes: bool,
+}
+
+// The rating change of a single player in a team game.
+struct PlayerChange {
+    player_id: String,
+    before: f64,
+    after: f64,
+}
+
+struct RatingChange {
+    white_before: f64,
+    white_after: f64,
+    black_before: f64,
+    black_after: f64,
+}
+
+struct ImportedGame {
+    line: u64,
+    white_player_id: String,
+    black_player_id: String,
+    result: MatchResult,
+    // An optional fourth column. It only tells duplicate rows apart for --dedup.
+    timestamp: String,
 }
 
 enum Operation {
+    Version,
     Help,
-    New,
-    AddPlayer(String),
+    New {
+        force: bool,
+    },
+    AddPlayer {
+        player_id: String,
+        rating: f64,
+    },
+    AddPlayers {
+        ids_filename: String,
+        rating: f64,
+    },
+    RemovePlayer(String),
+    Reset {
+        player_id: String,
+        clear_games: bool,
+    },
+    Rename {
+        old_id: String,
+        new_id: String,
+    },
+    Adjust {
+        player_id: String,
+        delta: f64,
+    },
     Update {
         white_player_id: String,
         black_player_id: String,
         result: MatchResult,
+        options: RatingOptions,
+        dry_run: bool,
+    },
+    BatchGames {
+        // Each game as "<white> <black> <result>", parsed as it's applied so that one bad
+        // game doesn't stop the others.
+        games: Vec<String>,
+        options: RatingOptions,
+        dry_run: bool,
+    },
+    TeamGame {
+        white_team: Vec<String>,
+        black_team: Vec<String>,
+        result: MatchResult,
+        options: RatingOptions,
+        dry_run: bool,
+    },
+    FreeForAll {
+        // Finishing order, winner first.
+        ranking: Vec<String>,
+        options: RatingOptions,
+        dry_run: bool,
+    },
+    Import {
+        games_filename: String,
+        dedup: bool,
+    },
+    View {
+        format: OutputFormat,
+        order: SortOrder,
+        options: ViewOptions,
+    },
+    Top {
+        n: usize,
+        format: OutputFormat,
+        precision: usize,
+    },
+    List(OutputFormat),
+    MostActive(usize),
+    Repl {
+        autosave: bool,
+    },
+    Prune {
+        below: f64,
+        dry_run: bool,
+    },
+    Inactive {
+        below: u32,
+        format: OutputFormat,
+    },
+    Show {
+        player_id: String,
+        precision: usize,
+    },
+    Stats(OutputFormat),
+    Verify,
+    RoundRobin,
+    ExpectedStandings {
+        // Every player in the table when None.
+        player_ids: Option<Vec<String>>,
+        scale: Option<f64>,
+    },
+    Swiss,
+    Predict {
+        player_a: String,
+        player_b: String,
+        scale: f64,
+    },
+    MatchOdds {
+        player_a: String,
+        player_b: String,
+        games: usize,
+        draw_rate: f64,
+        scale: f64,
+    },
+    Forecast {
+        player_id: String,
+        opponent_id: String,
+        result: MatchResult,
+        scale: f64,
+    },
+    WhatIf {
+        white_player_id: String,
+        black_player_id: String,
+        result: MatchResult,
+        k_values: [f64; 2],
+    },
+    Rebuild(RatingOptions),
+    Simulate {
+        players: usize,
+        games: usize,
+        seed: u64,
+    },
+    History {
+        since: Option<u64>,
+        limit: Option<usize>,
+        offset: usize,
     },
-    View,
+    Peak(String),
+    Drift,
+    Calibration,
+    Target {
+        player_id: String,
+        rank: usize,
+    },
+    HeadToHead {
+        player_a: String,
+        player_b: String,
+    },
+    Undo,
+    Decay,
+    Merge {
+        filename_a: String,
+        filename_b: String,
+        on_conflict: ConflictPolicy,
+    },
+}
+
+// Errors returned by leelo, grouped by category so that scripts can tell them apart by exit code.
+#[derive(Debug)]
+pub enum LeeloError {
+    // Invalid command line arguments.
+    Args(String),
+    // The table (or its history) couldn't be read or written.
+    Io(String),
+    // A player, or other requested data, wasn't found.
+    NotFound(String),
+    Other(String),
+}
+
+impl LeeloError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LeeloError::Other(_) => 1,
+            LeeloError::Args(_) => 2,
+            LeeloError::Io(_) => 3,
+            LeeloError::NotFound(_) => 4,
+        }
+    }
+
+    // Prefixes the message while keeping the category.
+    fn context(self, prefix: &str) -> LeeloError {
+        match self {
+            LeeloError::Args(m) => LeeloError::Args(format!("{}{}", prefix, m)),
+            LeeloError::Io(m) => LeeloError::Io(format!("{}{}", prefix, m)),
+            LeeloError::NotFound(m) => LeeloError::NotFound(format!("{}{}", prefix, m)),
+            LeeloError::Other(m) => LeeloError::Other(format!("{}{}", prefix, m)),
+        }
+    }
+}
+
+impl fmt::Display for LeeloError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LeeloError::Args(m)
+            | LeeloError::Io(m)
+            | LeeloError::NotFound(m)
+            | LeeloError::Other(m) => write!(f, "{}", m),
+        }
+    }
+}
+
+impl Error for LeeloError {}
+
+// Internally errors are boxed; this recovers their category at the boundary of the library.
+impl From<Box<dyn Error>> for LeeloError {
+    fn from(e: Box<dyn Error>) -> LeeloError {
+        let e = match e.downcast::<LeeloError>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        let is_io = e.is::<io::Error>()
+            || e.is::<rusqlite::Error>()
+            || e.downcast_ref::<csv::Error>()
+                .is_some_and(|e| e.is_io_error());
+        if is_io {
+            LeeloError::Io(e.to_string())
+        } else {
+            LeeloError::Other(e.to_string())
+        }
+    }
+}
+
+fn not_found(message: String) -> Box<dyn Error> {
+    Box::new(LeeloError::NotFound(message))
 }
 
 pub struct Config {
     filename: Option<String>,
+    store: StoreOptions,
+    // Suppresses status messages on stderr. Command output such as standings is still printed.
+    quiet: bool,
     operation: Operation,
 }
 
 impl Config {
-    pub fn new(args: &[String]) -> Result<Config, Box<dyn Error>> {
+    pub fn new(args: &[String]) -> Result<Config, LeeloError> {
+        Config::parse(args).map_err(|e| LeeloError::Args(e.to_string()))
+    }
+
+    fn parse(args: &[String]) -> Result<Config, Box<dyn Error>> {
         if args.len() < 2 {
             return Err("not enough arguments. Try leelo help.".into());
         }
 
+        let mut args = args.to_vec();
+        // --file may come before the command, so it's moved behind it for expect_args to find.
+        if let Some(file) = take_flag_value(&mut args, "--file")? {
+            args.extend(["--file".to_string(), file]);
+        }
+        let quiet = take_flag(&mut args, "--quiet");
+        let store = StoreOptions {
+            backend: match take_flag_value(&mut args, "--backend")? {
+                Some(name) => Some(Backend::parse(&name)?),
+                None => None,
+            },
+            backup: !take_flag(&mut args, "--no-backup"),
+            no_header: take_flag(&mut args, "--no-header"),
+            delimiter: match take_flag_value(&mut args, "--delimiter")? {
+                Some(value) => Some(parse_delimiter(&value)?),
+                None => None,
+            },
+        };
+        if args.len() < 2 {
+            return Err("not enough arguments. Try leelo help.".into());
+        }
         let command = args[1].clone();
         let mut filename = None;
 
         let operation = match command.as_str() {
             // leelo help
-            "help" | "h" => Operation::Help,
+            "help" | "h" | "--help" | "-h" => Operation::Help,
+
+            // leelo --version
+            "--version" | "-V" | "version" => Operation::Version,
 
             // leelo new <filename>
             "new" | "n" => {
-                if args.len() < 3 {
-                    return Err("not enough arguments for this command.".into());
-                }
+                let force = take_flag(&mut args, "--force");
+                expect_args(&mut args, "new <file>")?;
                 filename = Some(args[2].clone());
-                Operation::New
+                Operation::New { force }
             }
 
             // leelo player <player_id> <filename>
+            // leelo player --from <ids_filename> <filename>
             "player" | "p" => {
-                if args.len() < 4 {
-                    return Err("not enough arguments for this command.".into());
+                let initial = take_flag_value(&mut args, "--initial")?;
+                // The rating for this one player, e.g. one seeded from a performance rating.
+                let seed = take_flag_value(&mut args, "--rating")?;
+                if initial.is_some() && seed.is_some() {
+                    return Err("--rating and --initial cannot both be given.".into());
+                }
+                let rating = match initial.as_ref().or(seed.as_ref()) {
+                    Some(value) => parse_rating(value)?,
+                    None => INITIAL_RATING,
+                };
+                if let Some(ids_filename) = take_flag_value(&mut args, "--from")? {
+                    if seed.is_some() {
+                        return Err(
+                            "--rating sets a single player's rating and cannot be used with --from. Use --initial instead."
+                                .into(),
+                        );
+                    }
+                    expect_args(&mut args, "player --from <ids_filename> <file>")?;
+                    filename = Some(args[2].clone());
+                    Operation::AddPlayers {
+                        ids_filename,
+                        rating,
+                    }
+                } else {
+                    expect_args(&mut args, "player <player_id> <file>")?;
+                    filename = Some(args[3].clone());
+                    Operation::AddPlayer {
+                        player_id: args[2].clone(),
+                        rating,
+                    }
                 }
+            }
+
+            // leelo remove <player_id> <filename>
+            "remove" | "r" => {
+                expect_args(&mut args, "remove <player_id> <file>")?;
+                filename = Some(args[3].clone());
+                Operation::RemovePlayer(args[2].clone())
+            }
+
+            // leelo reset <player_id> <filename>
+            "reset" => {
+                let clear_games = take_flag(&mut args, "--clear-games");
+                expect_args(&mut args, "reset <player_id> <file>")?;
                 filename = Some(args[3].clone());
-                Operation::AddPlayer(args[2].clone())
+                Operation::Reset {
+                    player_id: args[2].clone(),
+                    clear_games,
+                }
+            }
+
+            // leelo rename <old_id> <new_id> <filename>
+            "rename" => {
+                expect_args(&mut args, "rename <old_id> <new_id> <file>")?;
+                filename = Some(args[4].clone());
+                Operation::Rename {
+                    old_id: args[2].clone(),
+                    new_id: args[3].clone(),
+                }
+            }
+
+            // leelo adjust <player_id> <delta> <filename>
+            "adjust" => {
+                expect_args(&mut args, "adjust <player_id> <delta> <file>")?;
+                filename = Some(args[4].clone());
+                Operation::Adjust {
+                    player_id: args[2].clone(),
+                    delta: parse_rating(&args[3])?,
+                }
             }
 
             // leelo game <white_player_id> <black_player_id> <result> <filename>
+            // leelo game --team <white_ids> <black_ids> <result> <filename>
+            // leelo game <filename> --batch *<game>
             "game" | "g" => {
-                if args.len() < 6 {
-                    return Err("not enough arguments for this command.".into());
-                }
-                let result = match args[4].as_str() {
-                    "1-0" => MatchResult::WhiteWin,
-                    "0-1" => MatchResult::BlackWin,
-                    "0.5-0.5" => MatchResult::Draw,
-                    _ => return Err("unable to interpret score argument.".into()),
+                // Everything after --batch is a game, apart from a --file moved to the end.
+                let batch = match args.iter().position(|arg| arg == "--batch") {
+                    Some(i) => {
+                        let mut games = args.split_off(i + 1);
+                        args.pop();
+                        if let Some(file) = take_flag_value(&mut games, "--file")? {
+                            args.extend(["--file".to_string(), file]);
+                        }
+                        Some(games)
+                    }
+                    None => None,
                 };
-                filename = Some(args[5].clone());
-                Operation::Update {
-                    white_player_id: args[2].clone(),
-                    black_player_id: args[3].clone(),
-                    result: result,
+                let options = parse_rating_options(&mut args)?;
+                let dry_run = take_flag(&mut args, "--dry-run");
+                if let Some(games) = batch {
+                    expect_args(&mut args, "game <file> --batch <games>")?;
+                    if games.is_empty() {
+                        return Err("missing games for `game --batch`.".into());
+                    }
+                    filename = Some(args[2].clone());
+                    Operation::BatchGames {
+                        games,
+                        options,
+                        dry_run,
+                    }
+                } else if let Some(white_ids) = take_flag_value(&mut args, "--team")? {
+                    if options.system == RatingSystem::Glicko {
+                        return Err("--team cannot be used with the glicko rating system.".into());
+                    }
+                    expect_args(
+                        &mut args,
+                        "game --team <white_ids> <black_ids> <result> <file>",
+                    )?;
+                    let result = MatchResult::parse(&args[3])?;
+                    filename = Some(args[4].clone());
+                    Operation::TeamGame {
+                        white_team: parse_team(&white_ids),
+                        black_team: parse_team(&args[2]),
+                        result,
+                        options,
+                        dry_run,
+                    }
+                } else {
+                    expect_args(
+                        &mut args,
+                        "game <white_player_id> <black_player_id> <result> <file>",
+                    )?;
+                    let result = MatchResult::parse(&args[4])?;
+                    filename = Some(args[5].clone());
+                    Operation::Update {
+                        white_player_id: args[2].clone(),
+                        black_player_id: args[3].clone(),
+                        result,
+                        options,
+                        dry_run,
+                    }
+                }
+            }
+
+            // leelo ffa <first> <second> *<player_id> <filename>
+            "ffa" => {
+                let options = parse_rating_options(&mut args)?;
+                if options.system == RatingSystem::Glicko {
+                    return Err("ffa cannot be used with the glicko rating system.".into());
+                }
+                if options.upset_bonus {
+                    return Err("--upset-bonus cannot be used with `ffa`.".into());
+                }
+                let dry_run = take_flag(&mut args, "--dry-run");
+                // The number of players varies, so the filename can only be told apart from
+                // them when it's last or given with --file.
+                if let Some(file) = take_flag_value(&mut args, "--file")? {
+                    args.push(file);
+                }
+                expect_args(&mut args, "ffa <first> <second> <file>")?;
+                filename = args.pop();
+                Operation::FreeForAll {
+                    ranking: args.split_off(2),
+                    options,
+                    dry_run,
+                }
+            }
+
+            // leelo import <games_filename> <filename>
+            "import" | "i" => {
+                let dedup = take_flag(&mut args, "--dedup");
+                expect_args(&mut args, "import <games_filename> <file>")?;
+                filename = Some(args[3].clone());
+                Operation::Import {
+                    games_filename: args[2].clone(),
+                    dedup,
                 }
             }
 
             // leelo view <filename>
             "view" | "v" => {
-                if args.len() < 3 {
-                    return Err("not enough arguments for this command.".into());
+                let format = parse_output_format(&mut args)?;
+                let options = ViewOptions {
+                    records: take_flag(&mut args, "--records"),
+                    combined: take_flag(&mut args, "--combined"),
+                    rank: take_flag(&mut args, "--rank"),
+                    precision: parse_precision(&mut args)?,
+                    color: take_flag(&mut args, "--color"),
+                    classes: take_flag(&mut args, "--classes"),
+                };
+                let order = match take_flag_value(&mut args, "--sort")? {
+                    Some(key) => SortOrder::parse(&key)?,
+                    None => SortOrder::RatingDesc,
                 };
+                expect_args(&mut args, "view <file>")?;
                 filename = Some(args[2].clone());
-                Operation::View
+                Operation::View {
+                    format,
+                    order,
+                    options,
+                }
             }
 
-            // leelo _ *<args>
-            _ => return Err("unknown command. Try leelo help.".into()),
-        };
-
-        Ok(Config {
-            filename,
-            operation,
-        })
-    }
-}
+            // leelo list <filename>
+            "list" | "l" => {
+                let format = parse_output_format(&mut args)?;
+                expect_args(&mut args, "list <file>")?;
+                filename = Some(args[2].clone());
+                Operation::List(format)
+            }
 
-fn read_to_hashmap(filename: &str, data: &mut HashMap<String, f64>) -> Result<(), Box<dyn Error>> {
-    let mut rdr = Reader::from_path(filename)?;
+            // leelo mostactive <filename>
+            "mostactive" => {
+                let n = match take_flag_value(&mut args, "--top")? {
+                    Some(value) => match value.parse::<usize>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err("number of players must be a positive integer.".into()),
+                    },
+                    None => 10,
+                };
+                expect_args(&mut args, "mostactive <file>")?;
+                filename = Some(args[2].clone());
+                Operation::MostActive(n)
+            }
 
-    for result in rdr.deserialize() {
-        let (player_id, rating): (String, f64) = result?;
-        (*data).insert(player_id, rating);
-    }
+            // leelo inactive <filename>
+            "inactive" => {
+                let below = match take_flag_value(&mut args, "--below")? {
+                    Some(value) => match value.parse::<u32>() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err("--below must be a positive integer.".into()),
+                    },
+                    None => 1,
+                };
+                let format = parse_output_format(&mut args)?;
+                expect_args(&mut args, "inactive <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Inactive { below, format }
+            }
 
-    Ok(())
-}
+            // leelo repl <filename>
+            "repl" => {
+                let autosave = !take_flag(&mut args, "--no-autosave");
+                expect_args(&mut args, "repl <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Repl { autosave }
+            }
 
-fn write_to_csv(filename: &str, data: &mut HashMap<String, f64>) -> Result<(), Box<dyn Error>> {
-    let mut wtr = Writer::from_path(filename)?;
+            // leelo prune --below <rating> <filename>
+            "prune" => {
+                let below = match take_flag_value(&mut args, "--below")? {
+                    Some(value) => parse_rating(&value)?,
+                    None => return Err("missing --below for `prune`.".into()),
+                };
+                let dry_run = take_flag(&mut args, "--dry-run");
+                expect_args(&mut args, "prune <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Prune { below, dry_run }
+            }
 
-    wtr.write_record(&["Player ID", "Rating"])?;
-    for (player_id, rating) in (*data).iter() {
-        let record = (player_id, rating);
-        wtr.serialize(record)?;
-        wtr.flush()?;
-    }
+            // leelo show <player_id> <filename>
+            "show" => {
+                let precision = parse_precision(&mut args)?;
+                expect_args(&mut args, "show <player_id> <file>")?;
+                filename = Some(args[3].clone());
+                Operation::Show {
+                    player_id: args[2].clone(),
+                    precision,
+                }
+            }
 
-    Ok(())
-}
+            // leelo verify <filename>
+            "verify" => {
+                expect_args(&mut args, "verify <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Verify
+            }
 
-fn create_player(player_id: String, data: &mut HashMap<String, f64>) -> Result<(), Box<dyn Error>> {
-    match (*data).entry(player_id) {
-        Entry::Occupied(_) => return Err("player_id already in use.".into()),
-        Entry::Vacant(v) => {
-            v.insert(INITIAL_RATING);
-        }
-    }
+            // leelo stats <filename>
+            "stats" => {
+                let format = parse_output_format(&mut args)?;
+                expect_args(&mut args, "stats <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Stats(format)
+            }
 
-    Ok(())
-}
+            // leelo roundrobin <filename>
+            "roundrobin" | "rr" => {
+                expect_args(&mut args, "roundrobin <file>")?;
+                filename = Some(args[2].clone());
+                Operation::RoundRobin
+            }
 
-fn update_ratings(
-    white_player_id: String,
-    black_player_id: String,
-    result: MatchResult,
-    data: &mut HashMap<String, f64>,
-) -> Result<(), Box<dyn Error>> {
-    let white_rating = match (*data).get(&white_player_id) {
-        Some(rat) => f64::from(*rat),
-        None => return Err("white player not found.".into()),
-    };
-    let black_rating = match (*data).get(&black_player_id) {
-        Some(rat) => f64::from(*rat),
-        None => return Err("black player not found.".into()),
-    };
-    let rating_difference = white_rating - black_rating;
-    let white_score_expected = 1. / (f64::exp(-rating_difference / RATING_CONST) + 1.);
-    let black_score_expected = 1. - white_score_expected;
+            // leelo expected <filename>
+            "expected" => {
+                let player_ids =
+                    take_flag_value(&mut args, "--players")?.map(|ids| parse_team(&ids));
+                let scale = parse_scale(&mut args)?;
+                expect_args(&mut args, "expected <file>")?;
+                filename = Some(args[2].clone());
+                Operation::ExpectedStandings { player_ids, scale }
+            }
 
-    let (white_score, black_score) = match result {
-        MatchResult::WhiteWin => (1., 0.),
-        MatchResult::BlackWin => (0., 1.),
-        MatchResult::Draw => (0.5, 0.5),
-    };
+            // leelo swiss <filename>
+            "swiss" => {
+                expect_args(&mut args, "swiss <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Swiss
+            }
+
+            // leelo top <n> <filename>
+            "top" | "t" => {
+                let format = parse_output_format(&mut args)?;
+                let precision = parse_precision(&mut args)?;
+                expect_args(&mut args, "top <n> <file>")?;
+                let n = match args[2].parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err("number of players must be a positive integer.".into()),
+                };
+                filename = Some(args[3].clone());
+                Operation::Top {
+                    n,
+                    format,
+                    precision,
+                }
+            }
+
+            // leelo predict <player_a> <player_b> <filename>
+            "predict" => {
+                let scale = parse_scale(&mut args)?.unwrap_or(RATING_CONST);
+                expect_args(&mut args, "predict <player_a> <player_b> <file>")?;
+                filename = Some(args[4].clone());
+                Operation::Predict {
+                    player_a: args[2].clone(),
+                    player_b: args[3].clone(),
+                    scale,
+                }
+            }
+
+            // leelo match-odds <player_a> <player_b> <games> <filename>
+            "match-odds" => {
+                let scale = parse_scale(&mut args)?.unwrap_or(RATING_CONST);
+                let draw_rate = match take_flag_value(&mut args, "--draw-rate")? {
+                    Some(value) => match value.parse::<f64>() {
+                        Ok(rate) if (0. ..=1.).contains(&rate) => rate,
+                        _ => {
+                            return Err("--draw-rate must be a probability between 0 and 1.".into())
+                        }
+                    },
+                    None => 0.,
+                };
+                expect_args(&mut args, "match-odds <player_a> <player_b> <games> <file>")?;
+                let games = match args[4].parse::<usize>() {
+                    Ok(games) if games > 0 => games,
+                    _ => return Err("number of games must be a positive integer.".into()),
+                };
+                filename = Some(args[5].clone());
+                Operation::MatchOdds {
+                    player_a: args[2].clone(),
+                    player_b: args[3].clone(),
+                    games,
+                    draw_rate,
+                    scale,
+                }
+            }
+
+            // leelo forecast <player_id> <opponent_id> <result> <filename>
+            "forecast" => {
+                let scale = parse_scale(&mut args)?.unwrap_or(RATING_CONST);
+                expect_args(
+                    &mut args,
+                    "forecast <player_id> <opponent_id> <result> <file>",
+                )?;
+                let result = MatchResult::parse(&args[4])?;
+                filename = Some(args[5].clone());
+                Operation::Forecast {
+                    player_id: args[2].clone(),
+                    opponent_id: args[3].clone(),
+                    result,
+                    scale,
+                }
+            }
+
+            // leelo whatif <white_player_id> <black_player_id> <result> <filename> --k1 <k> --k2 <k>
+            "whatif" => {
+                let mut k_values = [0.; 2];
+                for (k, flag) in k_values.iter_mut().zip(["--k1", "--k2"]) {
+                    *k = match take_flag_value(&mut args, flag)? {
+                        Some(value) => parse_k(&value)?,
+                        None => return Err(format!("missing {} for `whatif`.", flag).into()),
+                    };
+                }
+                expect_args(
+                    &mut args,
+                    "whatif <white_player_id> <black_player_id> <result> <file>",
+                )?;
+                let result = MatchResult::parse(&args[4])?;
+                filename = Some(args[5].clone());
+                Operation::WhatIf {
+                    white_player_id: args[2].clone(),
+                    black_player_id: args[3].clone(),
+                    result,
+                    k_values,
+                }
+            }
+
+            // leelo rebuild <filename>
+            "rebuild" => {
+                let options = parse_rating_options(&mut args)?;
+                expect_args(&mut args, "rebuild <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Rebuild(options)
+            }
+
+            // leelo simulate <n_players> <n_games>
+            "simulate" => {
+                let seed = match take_flag_value(&mut args, "--seed")? {
+                    Some(value) => match value.parse::<u64>() {
+                        Ok(seed) => seed,
+                        Err(_) => return Err("seed must be a non-negative integer.".into()),
+                    },
+                    None => now(),
+                };
+                expect_args(&mut args, "simulate <n_players> <n_games>")?;
+                let players = match args[2].parse::<usize>() {
+                    Ok(n) if n >= 2 => n,
+                    _ => return Err("number of players must be an integer of at least 2.".into()),
+                };
+                let games = match args[3].parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err("number of games must be a positive integer.".into()),
+                };
+                Operation::Simulate {
+                    players,
+                    games,
+                    seed,
+                }
+            }
+
+            // leelo history <filename> [--since <date>] [--limit <n>] [--offset <n>]
+            "history" => {
+                let since = match take_flag_value(&mut args, "--since")? {
+                    Some(date) => Some(parse_timestamp(&date).ok_or_else(|| {
+                        format!(
+                            "invalid date '{}' for --since. Expected an ISO-8601 date such as 2022-07-05.",
+                            date
+                        )
+                    })?),
+                    None => None,
+                };
+                let limit = match take_flag_value(&mut args, "--limit")? {
+                    Some(value) => match value.parse::<usize>() {
+                        Ok(n) if n > 0 => Some(n),
+                        _ => return Err("--limit must be a positive integer.".into()),
+                    },
+                    None => None,
+                };
+                let offset = match take_flag_value(&mut args, "--offset")? {
+                    Some(value) => match value.parse::<usize>() {
+                        Ok(n) => n,
+                        _ => return Err("--offset must be a non-negative integer.".into()),
+                    },
+                    None => 0,
+                };
+                expect_args(&mut args, "history <file>")?;
+                filename = Some(args[2].clone());
+                Operation::History {
+                    since,
+                    limit,
+                    offset,
+                }
+            }
+
+            // leelo target <player_id> <rank> <filename>
+            "target" => {
+                expect_args(&mut args, "target <player_id> <rank> <file>")?;
+                let rank = match args[3].parse::<usize>() {
+                    Ok(rank) if rank > 0 => rank,
+                    _ => return Err("rank must be a positive integer.".into()),
+                };
+                filename = Some(args[4].clone());
+                Operation::Target {
+                    player_id: args[2].clone(),
+                    rank,
+                }
+            }
+
+            // leelo calibration <filename>
+            "calibration" => {
+                expect_args(&mut args, "calibration <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Calibration
+            }
+
+            // leelo drift <filename>
+            "drift" => {
+                expect_args(&mut args, "drift <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Drift
+            }
+
+            // leelo peak <player_id> <filename>
+            "peak" => {
+                expect_args(&mut args, "peak <player_id> <file>")?;
+                filename = Some(args[3].clone());
+                Operation::Peak(args[2].clone())
+            }
+
+            // leelo h2h <player_a> <player_b> <filename>
+            "h2h" => {
+                expect_args(&mut args, "h2h <player_a> <player_b> <file>")?;
+                filename = Some(args[4].clone());
+                Operation::HeadToHead {
+                    player_a: args[2].clone(),
+                    player_b: args[3].clone(),
+                }
+            }
+
+            // leelo undo <filename>
+            "undo" | "u" => {
+                expect_args(&mut args, "undo <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Undo
+            }
+
+            // leelo decay <filename>
+            "decay" => {
+                expect_args(&mut args, "decay <file>")?;
+                filename = Some(args[2].clone());
+                Operation::Decay
+            }
+
+            // leelo merge <filename_a> <filename_b> <out_filename>
+            "merge" => {
+                let on_conflict = match take_flag_value(&mut args, "--on-conflict")? {
+                    Some(name) => ConflictPolicy::parse(&name)?,
+                    None => ConflictPolicy::Average,
+                };
+                expect_args(&mut args, "merge <filename_a> <filename_b> <out_filename>")?;
+                filename = Some(args[4].clone());
+                Operation::Merge {
+                    filename_a: args[2].clone(),
+                    filename_b: args[3].clone(),
+                    on_conflict,
+                }
+            }
+
+            // leelo _ *<args>
+            _ => return Err("unknown command. Try leelo help.".into()),
+        };
+
+        Ok(Config {
+            filename,
+            store,
+            quiet,
+            operation,
+        })
+    }
+}
 
-    let white_rating_change = K * (white_score - white_score_expected);
-    let black_rating_change = K * (black_score - black_score_expected);
-    let white_new_rating = white_rating + white_rating_change;
-    let black_new_rating = black_rating + black_rating_change;
+// Checks the positional arguments left once a command has taken its flags
+// against its usage, e.g. "rename <old_id> <new_id> <file>". Placeholders
+// following a flag in the usage belong to that flag and have already been taken.
+fn expect_args(args: &mut Vec<String>, usage: &str) -> Result<(), Box<dyn Error>> {
+    let mut words = usage.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let takes_file = usage.ends_with("<file>");
+    let file = if takes_file {
+        take_flag_value(args, "--file")?
+    } else {
+        None
+    };
+    if let Some(flag) = args.iter().skip(2).find(|arg| arg.starts_with("--")) {
+        return Err(format!("unknown flag {} for `{}`.", flag, command).into());
+    }
 
-    (*data).insert(white_player_id, white_new_rating);
-    (*data).insert(black_player_id, black_new_rating);
+    let mut positionals = Vec::new();
+    let mut previous = "";
+    for word in words {
+        if word.starts_with('<') && !previous.starts_with("--") {
+            positionals.push(word);
+        }
+        previous = word;
+    }
+    let required = positionals.len() + 2;
+    if takes_file {
+        default_filename(args, required, file.clone());
+    }
+    if let Some(file) = file {
+        if args.len() >= required && args[required - 1] != file {
+            return Err(format!(
+                "--file {} and filename {} disagree for `{}`.",
+                file,
+                args[required - 1],
+                command
+            )
+            .into());
+        }
+    }
+    if args.len() < required {
+        return Err(format!(
+            "missing {} for `{}`. Usage: leelo {}.",
+            positionals[args.len() - 2],
+            command,
+            usage
+        )
+        .into());
+    }
 
     Ok(())
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    match config.operation {
-        Operation::Help => {
-            println!("A simple Elo rating implementation.");
-            println!("");
-            println!("USAGE:");
-            println!("\tleelo [COMMAND] [ARGUMENTS]");
-            println!("");
-            println!("COMMANDS:");
-            println!("\thelp");
-            println!("\t\t\tPrint help information");
-            println!("\tnew <file>");
-            println!("\t\t\tCreate new leelo table");
-            println!("\tgame <white> <black> <score> <file>");
-            println!("\t\t\tRecord results of a game and update ratings");
-            println!("\tplayer <id> <file>");
-            println!("\t\t\tCreate new player");
-            println!("\tview <file>");
-            println!("\t\t\tView players and ratings");
+// The filename is always the last positional argument, so when exactly that one is
+// missing it is taken from --file, or failing that from LEELO_FILE. An explicit filename
+// always wins over LEELO_FILE.
+fn default_filename(args: &mut Vec<String>, required: usize, file: Option<String>) {
+    if args.len() + 1 == required {
+        if let Some(filename) = file.or_else(|| env::var("LEELO_FILE").ok()) {
+            args.push(filename);
         }
-        Operation::New => {
-            let mut data: HashMap<String, f64> = HashMap::new();
-            let filename = config.filename.unwrap();
-            write_to_csv(&filename, &mut data)?;
+    }
+}
+
+fn parse_precision(args: &mut Vec<String>) -> Result<usize, Box<dyn Error>> {
+    match take_flag_value(args, "--precision")? {
+        Some(value) => match value.parse::<usize>() {
+            Ok(precision) if precision <= 17 => Ok(precision),
+            _ => Err("--precision must be a whole number of decimal places up to 17.".into()),
+        },
+        None => Ok(0),
+    }
+}
+
+// Takes --format <name>, or the older --json and --csv shorthands for it.
+fn parse_output_format(args: &mut Vec<String>) -> Result<OutputFormat, Box<dyn Error>> {
+    let mut formats = Vec::new();
+    if let Some(name) = take_flag_value(args, "--format")? {
+        formats.push(OutputFormat::parse(&name)?);
+    }
+    if take_flag(args, "--json") {
+        formats.push(OutputFormat::Json);
+    }
+    if take_flag(args, "--csv") {
+        formats.push(OutputFormat::Csv);
+    }
+    match formats[..] {
+        [] => Ok(OutputFormat::Text),
+        [format] => Ok(format),
+        _ => Err("only one output format can be used at a time.".into()),
+    }
+}
+
+// Splits a comma-separated list of team members, e.g. "alice,bob".
+fn parse_team(ids: &str) -> Vec<String> {
+    ids.split(',').map(|id| id.trim().to_string()).collect()
+}
+
+// Parses a game given to `game --batch` as "<white> <black> <result>".
+fn parse_batch_game(game: &str) -> Result<(String, String, MatchResult), Box<dyn Error>> {
+    match game.split_whitespace().collect::<Vec<&str>>()[..] {
+        [white, black, result] => Ok((
+            white.to_string(),
+            black.to_string(),
+            MatchResult::parse(result)?,
+        )),
+        _ => Err("expected \"<white> <black> <result>\".".into()),
+    }
+}
+
+// Removes `flag` and the value following it from `args`, returning the value if the flag was given.
+fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => {
+            if i + 1 >= args.len() {
+                return Err(format!("missing value for {}.", flag).into());
+            }
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(value))
         }
-        Operation::Update {
-            white_player_id,
-            black_player_id,
-            result,
-        } => {
-            let mut data: HashMap<String, f64> = HashMap::new();
-            let filename = config.filename.unwrap();
-            read_to_hashmap(&filename, &mut data)?;
-            update_ratings(white_player_id, black_player_id, result, &mut data)?;
-            write_to_csv(&filename, &mut data)?;
+        None => Ok(None),
+    }
+}
+
+// Removes `flag` from `args`, returning whether it was given.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
         }
-        Operation::AddPlayer(player_id) => {
-            let mut data: HashMap<String, f64> = HashMap::new();
-            let filename = config.filename.unwrap();
-            read_to_hashmap(&filename, &mut data)?;
-            create_player(player_id, &mut data)?;
-            write_to_csv(&filename, &mut data)?;
+        None => false,
+    }
+}
+
+// Takes the flags that control how a game is rated out of `args`.
+fn parse_rating_options(args: &mut Vec<String>) -> Result<RatingOptions, Box<dyn Error>> {
+    let system = match take_flag_value(args, "--system")? {
+        Some(name) => RatingSystem::parse(&name)?,
+        None => RatingSystem::Elo,
+    };
+    let k = match take_flag_value(args, "--k")? {
+        Some(value) => Some(parse_k(&value)?),
+        None => None,
+    };
+    let margin = match take_flag_value(args, "--margin")? {
+        Some(value) => match value.parse::<f64>() {
+            Ok(margin) if margin.is_finite() && margin >= 0. => Some(margin),
+            _ => return Err("margin must be a non-negative number.".into()),
+        },
+        None => None,
+    };
+
+    if system == RatingSystem::Glicko {
+        if k.is_some() {
+            return Err("--k cannot be used with the glicko rating system.".into());
         }
-        Operation::View => {
-            let mut data: HashMap<String, f64> = HashMap::new();
-            let filename = config.filename.unwrap();
-            read_to_hashmap(&filename, &mut data)?;
+        if margin.is_some() {
+            return Err("--margin cannot be used with the glicko rating system.".into());
+        }
+    }
+    let scale = parse_scale(args)?;
+    if system == RatingSystem::Glicko && scale.is_some() {
+        return Err("--scale cannot be used with the glicko rating system.".into());
+    }
+    let weight = match take_flag_value(args, "--weight")? {
+        Some(value) => match value.parse::<f64>() {
+            Ok(weight) if weight.is_finite() && weight > 0. => Some(weight),
+            _ => return Err("weight must be a positive number.".into()),
+        },
+        None => None,
+    };
+    if system == RatingSystem::Glicko && weight.is_some() {
+        return Err("--weight cannot be used with the glicko rating system.".into());
+    }
+    let max_change = match take_flag_value(args, "--max-change")? {
+        Some(value) => match value.parse::<f64>() {
+            Ok(points) if points.is_finite() && points >= 0. => Some(points),
+            _ => return Err("--max-change must be a non-negative number of points.".into()),
+        },
+        None => None,
+    };
+    if system == RatingSystem::Glicko && max_change.is_some() {
+        return Err("--max-change cannot be used with the glicko rating system.".into());
+    }
+    let upset_bonus = take_flag(args, "--upset-bonus");
+    if system == RatingSystem::Glicko && upset_bonus {
+        return Err("--upset-bonus cannot be used with the glicko rating system.".into());
+    }
+    let no_draw_change = match take_flag_value(args, "--no-draw-change")? {
+        Some(value) => match value.parse::<f64>() {
+            Ok(points) if points.is_finite() && points >= 0. => Some(points),
+            _ => return Err("--no-draw-change must be a non-negative number of points.".into()),
+        },
+        None => None,
+    };
+    if system == RatingSystem::Glicko && no_draw_change.is_some() {
+        return Err("--no-draw-change cannot be used with the glicko rating system.".into());
+    }
+    let floor = match take_flag_value(args, "--floor")? {
+        Some(value) => Some(parse_rating(&value)?),
+        None => None,
+    };
+    let ceiling = match take_flag_value(args, "--ceiling")? {
+        Some(value) => Some(parse_rating(&value)?),
+        None => None,
+    };
+    if system == RatingSystem::Glicko && (floor.is_some() || ceiling.is_some()) {
+        return Err("--floor and --ceiling cannot be used with the glicko rating system.".into());
+    }
+    if let (Some(floor), Some(ceiling)) = (floor, ceiling) {
+        if floor > ceiling {
+            return Err("--floor must not be above --ceiling.".into());
+        }
+    }
 
-            let mut data_vec: Vec<(&String, &f64)> = data.iter().collect();
-            data_vec.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap());
+    Ok(RatingOptions {
+        system,
+        k,
+        margin,
+        floor,
+        ceiling,
+        scale,
+        no_draw_change,
+        weight,
+        max_change,
+        upset_bonus,
+    })
+}
 
-            let mut max_player_id_len = 0;
-            for (player_id, _) in &data_vec {
-                max_player_id_len = cmp::max(max_player_id_len, (*player_id).len());
-            }
-            for (player_id, rating) in &data_vec {
-                let tabs = max_player_id_len / 8 + 1;
-                print!(
-                    "{}\r{}{}\n",
-                    player_id,
-                    "\t".repeat(tabs),
-                    (**rating).round() as u32
-                );
-                io::stdout().flush()?;
-            }
+fn parse_scale(args: &mut Vec<String>) -> Result<Option<f64>, Box<dyn Error>> {
+    match take_flag_value(args, "--scale")? {
+        Some(value) => match value.parse::<f64>() {
+            Ok(scale) if scale.is_finite() && scale > 0. => Ok(Some(scale)),
+            _ => Err("scale must be a positive number.".into()),
+        },
+        None => Ok(None),
+    }
+}
+
+fn parse_k(value: &str) -> Result<f64, Box<dyn Error>> {
+    match value.parse::<f64>() {
+        Ok(k) if k.is_finite() && k >= 0. => Ok(k),
+        _ => Err("K-factor must be a non-negative number.".into()),
+    }
+}
+
+fn parse_delimiter(value: &str) -> Result<u8, Box<dyn Error>> {
+    match value.as_bytes() {
+        // A quote or line break would be read as part of the CSV syntax instead.
+        [byte] if !matches!(byte, b'"' | b'\n' | b'\r') => Ok(*byte),
+        _ => Err(format!(
+            "--delimiter must be a single character such as ';', got '{}'.",
+            value
+        )
+        .into()),
+    }
+}
+
+fn parse_rating(value: &str) -> Result<f64, Box<dyn Error>> {
+    match value.parse::<f64>() {
+        Ok(rating) if rating.is_finite() => Ok(rating),
+        _ => Err(format!("rating must be a finite number, got '{}'.", value).into()),
+    }
+}
+
+// Version of the CSV table layout, written to the Version column of every row. Tables from
+// before the column was added are version 1, which may lack any of the columns after Rating.
+// Version 3 added Last Played.
+// Older versions are read with defaults for the missing columns and written back as the
+// current version.
+const TABLE_VERSION: u32 = 3;
+
+fn read_to_hashmap(
+    filename: &str,
+    has_headers: bool,
+    delimiter: u8,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    for_each_table_row(filename, has_headers, delimiter, |player_id, player| {
+        // A later row would otherwise silently replace the earlier one.
+        if (*data).contains_key(&player_id) {
+            return Err(format!("duplicate player id {} in table.", player_id).into());
         }
+        (*data).insert(player_id, player);
+        Ok(())
+    })
+}
+
+// Parses a CSV table one row at a time, so that callers which only need part of it never hold
+// the whole table in memory.
+fn for_each_table_row<F>(
+    filename: &str,
+    has_headers: bool,
+    delimiter: u8,
+    mut f: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(String, PlayerRecord) -> Result<(), Box<dyn Error>>,
+{
+    // Flexible so that tables written before the later columns were added still load.
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .has_headers(has_headers)
+        .delimiter(delimiter)
+        .from_reader(open_table(filename)?);
+
+    let mut record = csv::StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        let (player_id, player) = parse_table_row(&record)?;
+        f(player_id, player)?;
+    }
+
+    Ok(())
+}
+
+// Tables stored as .gz are compressed with gzip; everything else is plain CSV.
+fn is_gzip(filename: &str) -> bool {
+    filename.ends_with(".gz")
+}
+
+// Opens a CSV table for reading, decompressing it on the fly if it is gzipped.
+fn open_table(filename: &str) -> Result<Box<dyn io::Read>, Box<dyn Error>> {
+    let file = match File::open(filename) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(missing_table(filename)),
+        Err(e) => return Err(e.into()),
+    };
+    let file = io::BufReader::new(file);
+    if is_gzip(filename) {
+        Ok(Box::new(GzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+fn parse_table_row(record: &csv::StringRecord) -> Result<(String, PlayerRecord), Box<dyn Error>> {
+    let version = match record.get(9) {
+        Some(version) => version
+            .parse::<u32>()
+            .map_err(|_| format!("invalid table version '{}'.", version))?,
+        None => 1,
+    };
+    if version > TABLE_VERSION {
+        return Err(format!(
+            "table uses version {} of the format, but this leelo only understands up to version {}. Upgrade leelo to read it.",
+            version, TABLE_VERSION
+        )
+        .into());
+    }
+    let player_id = match record.get(0) {
+        Some(id) => id.to_string(),
+        None => return Err("missing player id.".into()),
     };
+    let rating: f64 = match record.get(1) {
+        Some(rat) => rat.parse()?,
+        None => return Err("missing rating.".into()),
+    };
+    // Tables from before colors were rated separately only have the combined rating.
+    let white_rating = parse_optional_rating(record.get(6))?.unwrap_or(rating);
+    let black_rating = parse_optional_rating(record.get(7))?.unwrap_or(rating);
+    Ok((
+        player_id,
+        PlayerRecord {
+            white_rating,
+            black_rating,
+            rd: parse_optional_rating(record.get(8))?.unwrap_or(GLICKO_INITIAL_RD),
+            games: parse_count(record.get(2))?,
+            wins: parse_count(record.get(3))?,
+            losses: parse_count(record.get(4))?,
+            draws: parse_count(record.get(5))?,
+            last_played: record
+                .get(10)
+                .filter(|time| !time.is_empty())
+                .map(String::from),
+        },
+    ))
+}
+
+// Problems with a single player's record that `verify` reports.
+fn player_problems(player_id: &str, player: &PlayerRecord) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (name, value) in [
+        ("White rating", player.white_rating),
+        ("Black rating", player.black_rating),
+        ("RD", player.rd),
+    ] {
+        if !value.is_finite() {
+            problems.push(format!("{} of {} is not a finite number.", name, player_id));
+        }
+    }
+    // Tables from before records were kept have no wins, losses or draws to check.
+    let record = player.wins + player.losses + player.draws;
+    if record > 0 && record != player.games {
+        problems.push(format!(
+            "{} has {} games but a record of +{} -{} ={}.",
+            player_id, player.games, player.wins, player.losses, player.draws
+        ));
+    }
+    problems
+}
+
+// Points first-time users at `leelo new` instead of reporting a bare I/O error.
+fn missing_table(filename: &str) -> Box<dyn Error> {
+    Box::new(LeeloError::Io(format!(
+        "no table at {}. Create one with `leelo new {}`.",
+        filename, filename
+    )))
+}
+
+// Parses an optional count column, which is absent in older tables.
+fn parse_count(field: Option<&str>) -> Result<u32, Box<dyn Error>> {
+    match field {
+        Some(count) if !count.is_empty() => Ok(count.parse()?),
+        _ => Ok(0),
+    }
+}
+
+fn write_to_csv(
+    filename: &str,
+    has_headers: bool,
+    delimiter: u8,
+    data: &HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    let mut builder = WriterBuilder::new();
+    builder.delimiter(delimiter);
+    write_atomically(filename, |file| {
+        if is_gzip(filename) {
+            let mut encoder = GzEncoder::new(file, Compression::default());
+            write_table_rows(&mut builder.from_writer(&mut encoder), has_headers, data)?;
+            encoder.finish()?;
+        } else {
+            write_table_rows(&mut builder.from_writer(file), has_headers, data)?;
+        }
+        Ok(())
+    })
+}
+
+fn write_table_rows<W: io::Write>(
+    wtr: &mut Writer<W>,
+    has_headers: bool,
+    data: &HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    if has_headers {
+        wtr.write_record([
+            "Player ID",
+            "Rating",
+            "Games",
+            "Wins",
+            "Losses",
+            "Draws",
+            "White Rating",
+            "Black Rating",
+            "RD",
+            "Version",
+            "Last Played",
+        ])?;
+    }
+    // Floats are written in their shortest form that parses back to the same f64, so ratings
+    // survive any number of read/write cycles exactly.
+    for (player_id, player) in (*data).iter() {
+        let record = (
+            player_id,
+            player.rating(),
+            player.games,
+            player.wins,
+            player.losses,
+            player.draws,
+            player.white_rating,
+            player.black_rating,
+            player.rd,
+            TABLE_VERSION,
+            &player.last_played,
+        );
+        wtr.serialize(record)?;
+        wtr.flush()?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+// Writes to a temporary file next to `filename` and renames it into place once `write` succeeds,
+// so a crash or error part way through never leaves a truncated file behind.
+fn write_atomically<F>(filename: &str, write: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&mut File) -> Result<(), Box<dyn Error>>,
+{
+    let tmp_filename = format!("{}.tmp", filename);
+    let mut file = File::create(&tmp_filename)?;
+
+    if let Err(e) = write(&mut file).and_then(|_| Ok(file.sync_all()?)) {
+        let _ = fs::remove_file(&tmp_filename);
+        return Err(e);
+    }
+    fs::rename(&tmp_filename, filename)?;
 
     Ok(())
 }
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Backend {
+    Csv,
+    Sqlite,
+    #[cfg(test)]
+    Memory,
+}
+
+impl Backend {
+    fn parse(name: &str) -> Result<Backend, Box<dyn Error>> {
+        match name {
+            "csv" => Ok(Backend::Csv),
+            "sqlite" => Ok(Backend::Sqlite),
+            #[cfg(test)]
+            "memory" => Ok(Backend::Memory),
+            _ => Err(format!("unknown backend '{}'. Expected csv or sqlite.", name).into()),
+        }
+    }
+}
+
+// Where a ratings table is stored. Every command goes through this so that it works the same on
+// each backend.
+trait Store {
+    fn read(&self, data: &mut HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>>;
+    fn write(&self, data: &HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>>;
+
+    // Every problem found in the table, for `verify`. A table that can't be read at all is an
+    // error rather than a problem.
+    fn verify(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut data = HashMap::new();
+        self.read(&mut data)?;
+        Ok(sort_standings(&data, SortOrder::Name)
+            .into_iter()
+            .flat_map(|(player_id, player)| player_problems(player_id, player))
+            .collect())
+    }
+
+    // The `n` highest-rated players, best first. Backends that can avoid loading the whole
+    // table should override this.
+    fn top(&self, n: usize) -> Result<Vec<(String, PlayerRecord)>, Box<dyn Error>> {
+        let mut data = HashMap::new();
+        self.read(&mut data)?;
+        let top: Vec<String> = sort_standings(&data, SortOrder::RatingDesc)
+            .into_iter()
+            .take(n)
+            .map(|(player_id, _)| player_id.clone())
+            .collect();
+        Ok(top
+            .into_iter()
+            .map(|player_id| {
+                let player = data.remove(&player_id).unwrap();
+                (player_id, player)
+            })
+            .collect())
+    }
+}
+
+// A row kept by CsvStore::top. Entries compare greater the further down the standings they
+// are, so the heap's top is the first to drop: lower ratings, then NaN ratings, sort below
+// higher ones, and among equal ratings the earlier row ranks higher.
+struct TopEntry {
+    index: usize,
+    player_id: String,
+    player: PlayerRecord,
+}
+
+impl TopEntry {
+    fn key(&self) -> (bool, f64) {
+        let rating = self.player.rating();
+        (!rating.is_nan(), if rating.is_nan() { 0. } else { rating })
+    }
+}
+
+impl Ord for TopEntry {
+    fn cmp(&self, other: &TopEntry) -> Ordering {
+        let ((self_rated, self_rating), (other_rated, other_rating)) = (self.key(), other.key());
+        other_rated
+            .cmp(&self_rated)
+            .then_with(|| other_rating.total_cmp(&self_rating))
+            .then_with(|| self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for TopEntry {
+    fn partial_cmp(&self, other: &TopEntry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TopEntry {
+    fn eq(&self, other: &TopEntry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TopEntry {}
+
+struct CsvStore {
+    filename: String,
+    backup: bool,
+    no_header: bool,
+    delimiter: u8,
+}
+
+impl Store for CsvStore {
+    fn read(&self, data: &mut HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>> {
+        read_to_hashmap(&self.filename, !self.no_header, self.delimiter, data)
+    }
+
+    // Checks the rows one at a time so that a bad row is reported with its line number and
+    // doesn't hide problems further down.
+    fn verify(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut rdr = ReaderBuilder::new()
+            .flexible(true)
+            .has_headers(!self.no_header)
+            .delimiter(self.delimiter)
+            .from_reader(open_table(&self.filename)?);
+
+        let mut problems = Vec::new();
+        let mut seen: HashMap<String, u64> = HashMap::new();
+        let mut record = csv::StringRecord::new();
+        loop {
+            match rdr.read_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                // The reader can't resume after a malformed record.
+                Err(e) => {
+                    problems.push(format!("unreadable row: {}", e));
+                    break;
+                }
+            }
+            let line = record.position().map_or(0, |p| p.line());
+            let (player_id, player) = match parse_table_row(&record) {
+                Ok(row) => row,
+                Err(e) => {
+                    problems.push(format!("line {}: {}", line, e));
+                    continue;
+                }
+            };
+            if let Some(first) = seen.get(&player_id) {
+                problems.push(format!(
+                    "line {}: duplicate player id {}, first seen on line {}.",
+                    line, player_id, first
+                ));
+                continue;
+            }
+            problems.extend(
+                player_problems(&player_id, &player)
+                    .into_iter()
+                    .map(|problem| format!("line {}: {}", line, problem)),
+            );
+            seen.insert(player_id, line);
+        }
+
+        Ok(problems)
+    }
+
+    // Streams the table through a heap of the best `n` rows seen so far, so memory use depends
+    // on `n` rather than the size of the table. Unlike `read`, this doesn't detect duplicate
+    // ids, which would need every id kept in memory.
+    fn top(&self, n: usize) -> Result<Vec<(String, PlayerRecord)>, Box<dyn Error>> {
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        let mut index = 0;
+        for_each_table_row(
+            &self.filename,
+            !self.no_header,
+            self.delimiter,
+            |player_id, player| {
+                heap.push(TopEntry {
+                    index,
+                    player_id,
+                    player,
+                });
+                index += 1;
+                if heap.len() > n {
+                    heap.pop();
+                }
+                Ok(())
+            },
+        )?;
+        Ok(heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|entry| (entry.player_id, entry.player))
+            .collect())
+    }
+
+    fn write(&self, data: &HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>> {
+        if self.backup {
+            backup_file(&self.filename)?;
+        }
+        write_to_csv(&self.filename, !self.no_header, self.delimiter, data)
+    }
+}
+
+struct SqliteStore {
+    filename: String,
+    backup: bool,
+}
+
+impl Store for SqliteStore {
+    fn read(&self, data: &mut HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>> {
+        // Opened without SQLITE_OPEN_CREATE so that a mistyped path is an error rather than an
+        // empty table.
+        if !Path::new(&self.filename).exists() {
+            return Err(missing_table(&self.filename));
+        }
+        let conn = Connection::open_with_flags(&self.filename, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT player_id, white_rating, black_rating, rd, games, wins, losses, draws, {}
+                 FROM players",
+            // Databases written before the column was added don't have it yet.
+            if sqlite_has_last_played(&conn)? {
+                "last_played"
+            } else {
+                "NULL"
+            }
+        ))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                PlayerRecord {
+                    white_rating: row.get(1)?,
+                    black_rating: row.get(2)?,
+                    rd: row.get(3)?,
+                    games: row.get(4)?,
+                    wins: row.get(5)?,
+                    losses: row.get(6)?,
+                    draws: row.get(7)?,
+                    last_played: row.get(8)?,
+                },
+            ))
+        })?;
+        for row in rows {
+            let (player_id, player) = row?;
+            (*data).insert(player_id, player);
+        }
+
+        Ok(())
+    }
+
+    fn write(&self, data: &HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>> {
+        if self.backup {
+            backup_file(&self.filename)?;
+        }
+        let mut conn = Connection::open(&self.filename)?;
+        // Replacing every row inside one transaction keeps the write atomic, like the CSV backend.
+        let tx = conn.transaction()?;
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS players (
+                player_id TEXT PRIMARY KEY,
+                rating REAL NOT NULL,
+                white_rating REAL NOT NULL,
+                black_rating REAL NOT NULL,
+                rd REAL NOT NULL,
+                games INTEGER NOT NULL,
+                wins INTEGER NOT NULL,
+                losses INTEGER NOT NULL,
+                draws INTEGER NOT NULL,
+                last_played TEXT
+            )",
+            [],
+        )?;
+        if !sqlite_has_last_played(&tx)? {
+            tx.execute("ALTER TABLE players ADD COLUMN last_played TEXT", [])?;
+        }
+        tx.execute("DELETE FROM players", [])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO players
+                 (player_id, rating, white_rating, black_rating, rd, games, wins, losses, draws,
+                  last_played)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for (player_id, player) in (*data).iter() {
+                stmt.execute(params![
+                    player_id,
+                    player.rating(),
+                    player.white_rating,
+                    player.black_rating,
+                    player.rd,
+                    player.games,
+                    player.wins,
+                    player.losses,
+                    player.draws,
+                    player.last_played,
+                ])?;
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    // The tables held by MemoryStore, by filename. Each test runs on its own thread, so tests
+    // never see each other's tables.
+    static MEMORY_TABLES: std::cell::RefCell<HashMap<String, HashMap<String, PlayerRecord>>> =
+        std::cell::RefCell::new(HashMap::new());
+}
+
+// Keeps the ratings table in memory, so that tests can run commands with `--backend memory`
+// without the table reaching disk. A table exists once something has written it, e.g. `new`.
+// This covers table storage only: locks and the history aren't part of the store, so commands
+// that take a lock or log a game still create those files next to `filename`.
+#[cfg(test)]
+struct MemoryStore {
+    filename: String,
+}
+
+#[cfg(test)]
+impl Store for MemoryStore {
+    fn read(&self, data: &mut HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>> {
+        MEMORY_TABLES.with(|tables| match tables.borrow().get(&self.filename) {
+            Some(table) => {
+                (*data).extend(
+                    table
+                        .iter()
+                        .map(|(id, player)| (id.clone(), player.clone())),
+                );
+                Ok(())
+            }
+            None => Err(missing_table(&self.filename)),
+        })
+    }
+
+    fn write(&self, data: &HashMap<String, PlayerRecord>) -> Result<(), Box<dyn Error>> {
+        MEMORY_TABLES.with(|tables| {
+            tables
+                .borrow_mut()
+                .insert(self.filename.clone(), (*data).clone())
+        });
+        Ok(())
+    }
+}
+
+fn sqlite_has_last_played(conn: &Connection) -> Result<bool, Box<dyn Error>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('players')")?;
+    let mut names = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    Ok(names.any(|name| name.is_ok_and(|name| name == "last_played")))
+}
+
+// An advisory lock on a table, held by creating <file>.lock and released by removing it when
+// dropped. Commands that read, modify and write the table hold it throughout so that two
+// concurrent runs can't lose each other's updates.
+struct TableLock {
+    filename: String,
+}
+
+impl TableLock {
+    fn acquire(filename: &str) -> Result<TableLock, Box<dyn Error>> {
+        let lock_filename = format!("{}.lock", filename);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_filename)
+        {
+            Ok(_) => Ok(TableLock {
+                filename: lock_filename,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(format!(
+                "table is busy. If no other leelo command is running, remove {}.",
+                lock_filename
+            )
+            .into()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for TableLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.filename);
+    }
+}
+
+fn backup_filename(filename: &str) -> String {
+    format!("{}.bak", filename)
+}
+
+// Copies the table to <file>.bak before it is overwritten, so that the previous state can be
+// restored by hand. There is nothing to back up when the table is being created.
+fn backup_file(filename: &str) -> Result<(), Box<dyn Error>> {
+    if Path::new(filename).exists() {
+        fs::copy(filename, backup_filename(filename))?;
+    }
+
+    Ok(())
+}
+
+// Global settings for how tables are stored, from the --backend, --no-backup and --no-header flags.
+#[derive(Clone, Copy, Default)]
+struct StoreOptions {
+    backend: Option<Backend>,
+    // Copies the table to <file>.bak before it is overwritten. On unless --no-backup is given.
+    backup: bool,
+    // Reads and writes CSV tables that have no header row, as exported by some other tools. A
+    // header is expected and written by default.
+    no_header: bool,
+    // Separates the fields of CSV tables, e.g. b';' for spreadsheets in locales that use a
+    // decimal comma. A comma if not given.
+    delimiter: Option<u8>,
+}
+
+// Picks the backend from an explicit --backend flag, falling back to the file extension.
+fn open_store(filename: &str, options: &StoreOptions) -> Box<dyn Store> {
+    let backup = options.backup;
+    let backend = options.backend.unwrap_or_else(|| {
+        if filename.ends_with(".db") {
+            Backend::Sqlite
+        } else {
+            Backend::Csv
+        }
+    });
+    let filename = filename.to_string();
+    match backend {
+        Backend::Csv => Box::new(CsvStore {
+            filename,
+            backup,
+            no_header: options.no_header,
+            delimiter: options.delimiter.unwrap_or(b','),
+        }),
+        Backend::Sqlite => Box::new(SqliteStore { filename, backup }),
+        #[cfg(test)]
+        Backend::Memory => Box::new(MemoryStore { filename }),
+    }
+}
+
+// Reads white,black,result rows from a headerless CSV at `games_filename`, or from stdin if it
+// is "-" so results can be piped in.
+fn read_games(games_filename: &str) -> Result<Vec<ImportedGame>, Box<dyn Error>> {
+    let mut builder = ReaderBuilder::new();
+    builder.has_headers(false).flexible(true);
+    if games_filename == "-" {
+        parse_games(builder.from_reader(io::stdin()))
+    } else {
+        parse_games(builder.from_path(games_filename)?)
+    }
+}
+
+fn parse_games<R: io::Read>(mut rdr: csv::Reader<R>) -> Result<Vec<ImportedGame>, Box<dyn Error>> {
+    let mut games = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        let line = record.position().map_or(0, |pos| pos.line());
+        if record.len() < 3 {
+            return Err(format!("line {}: expected white,black,result.", line).into());
+        }
+        let result = MatchResult::parse(&record[2]).map_err(|e| format!("line {}: {}", line, e))?;
+        games.push(ImportedGame {
+            line,
+            white_player_id: record[0].to_string(),
+            black_player_id: record[1].to_string(),
+            result,
+            timestamp: record.get(3).unwrap_or_default().to_string(),
+        });
+    }
+
+    Ok(games)
+}
+
+// Drops every game that repeats an earlier one's white, black, result and timestamp, keeping
+// the first. Returns the remaining games and how many were dropped.
+fn dedup_games(games: Vec<ImportedGame>) -> (Vec<ImportedGame>, usize) {
+    let mut seen = HashSet::new();
+    let total = games.len();
+    let unique: Vec<ImportedGame> = games
+        .into_iter()
+        .filter(|game| {
+            seen.insert((
+                game.white_player_id.clone(),
+                game.black_player_id.clone(),
+                game.result.to_string(),
+                game.timestamp.clone(),
+            ))
+        })
+        .collect();
+    let duplicates = total - unique.len();
+    (unique, duplicates)
+}
+
+// Games are logged to a sibling file so that the ratings table itself keeps its simple format.
+fn history_filename(filename: &str) -> String {
+    format!("{}.history.csv", filename)
+}
+
+const HISTORY_HEADER: [&str; 9] = [
+    "White",
+    "Black",
+    "Result",
+    "Timestamp",
+    "White Rating",
+    "Black Rating",
+    "White RD",
+    "Black RD",
+    "Expected Score",
+];
+
+fn write_history_record<W: io::Write>(
+    wtr: &mut Writer<W>,
+    record: &HistoryRecord,
+) -> Result<(), Box<dyn Error>> {
+    let rating_field = |rating: Option<f64>| rating.map(|r| r.to_string()).unwrap_or_default();
+    let rating_list = |ratings: &[f64]| {
+        ratings
+            .iter()
+            .map(f64::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    match record {
+        HistoryRecord::Game(entry) => wtr.write_record([
+            entry.white_player_id.clone(),
+            entry.black_player_id.clone(),
+            entry.result.to_string(),
+            entry.timestamp.clone(),
+            rating_field(entry.white_rating),
+            rating_field(entry.black_rating),
+            rating_field(entry.white_rd),
+            rating_field(entry.black_rd),
+            rating_field(entry.expected_score),
+        ])?,
+        HistoryRecord::Adjustment(adjustment) => wtr.write_record([
+            adjustment.player_id.clone(),
+            String::new(),
+            format!("{:+}", adjustment.delta),
+            adjustment.timestamp.clone(),
+            adjustment.white_rating.to_string(),
+            adjustment.black_rating.to_string(),
+            String::new(),
+            String::new(),
+            String::new(),
+        ])?,
+        HistoryRecord::TeamGame(game) => wtr.write_record([
+            game.white_team.join(","),
+            game.black_team.join(","),
+            format!("team {}", game.result),
+            game.timestamp.clone(),
+            rating_list(&game.white_ratings),
+            rating_list(&game.black_ratings),
+            String::new(),
+            String::new(),
+            String::new(),
+        ])?,
+        HistoryRecord::FreeForAll(game) => wtr.write_record([
+            game.ranking.join(","),
+            String::new(),
+            "ffa".to_string(),
+            game.timestamp.clone(),
+            rating_list(&game.white_ratings),
+            rating_list(&game.black_ratings),
+            String::new(),
+            String::new(),
+            String::new(),
+        ])?,
+        HistoryRecord::Decay(decay) => wtr.write_record([
+            decay.player_ids.join(","),
+            String::new(),
+            "decay".to_string(),
+            decay.timestamp.clone(),
+            rating_list(&decay.white_ratings),
+            rating_list(&decay.black_ratings),
+            String::new(),
+            String::new(),
+            String::new(),
+        ])?,
+    }
+
+    Ok(())
+}
+
+fn append_history(filename: &str, record: &HistoryRecord) -> Result<(), Box<dyn Error>> {
+    let history_filename = history_filename(filename);
+    let is_new = !Path::new(&history_filename).exists();
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history_filename)?;
+    let mut wtr = Writer::from_writer(file);
+
+    if is_new {
+        wtr.write_record(HISTORY_HEADER)?;
+    }
+    write_history_record(&mut wtr, record)?;
+    wtr.flush()?;
+
+    Ok(())
+}
+
+fn write_history(filename: &str, history: &[HistoryRecord]) -> Result<(), Box<dyn Error>> {
+    write_atomically(&history_filename(filename), |file| {
+        let mut wtr = Writer::from_writer(file);
+
+        wtr.write_record(HISTORY_HEADER)?;
+        for record in history {
+            write_history_record(&mut wtr, record)?;
+        }
+        wtr.flush()?;
+
+        Ok(())
+    })
+}
+
+// The two-player games in the history, leaving out manual adjustments, decays and team and
+// free-for-all games.
+fn read_history(filename: &str) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+    Ok(read_history_records(filename)?
+        .into_iter()
+        .filter_map(|record| match record {
+            HistoryRecord::Game(entry) => Some(entry),
+            _ => None,
+        })
+        .collect())
+}
+
+fn read_history_records(filename: &str) -> Result<Vec<HistoryRecord>, Box<dyn Error>> {
+    let history_filename = history_filename(filename);
+    if !Path::new(&history_filename).exists() {
+        return Err(not_found("no history recorded for this table.".to_string()));
+    }
+    let mut rdr = ReaderBuilder::new()
+        .flexible(true)
+        .from_path(&history_filename)?;
+
+    let mut history = Vec::new();
+    for result in rdr.records() {
+        let record = result?;
+        if record.len() < 4 {
+            return Err("malformed history record.".into());
+        }
+        if let Some(result) = record[2].strip_prefix("team ") {
+            let white_team = parse_team(&record[0]);
+            let black_team = parse_team(&record[1]);
+            history.push(HistoryRecord::TeamGame(TeamGame {
+                result: MatchResult::parse(result)?,
+                timestamp: record[3].to_string(),
+                white_ratings: parse_rating_list(record.get(4), white_team.len())?,
+                black_ratings: parse_rating_list(record.get(5), black_team.len())?,
+                white_team,
+                black_team,
+            }));
+            continue;
+        }
+        if &record[2] == "ffa" {
+            let ranking = parse_team(&record[0]);
+            history.push(HistoryRecord::FreeForAll(FreeForAllGame {
+                timestamp: record[3].to_string(),
+                white_ratings: parse_rating_list(record.get(4), ranking.len())?,
+                black_ratings: parse_rating_list(record.get(5), ranking.len())?,
+                ranking,
+            }));
+            continue;
+        }
+        if &record[2] == "decay" {
+            let player_ids = parse_team(&record[0]);
+            history.push(HistoryRecord::Decay(Decay {
+                timestamp: record[3].to_string(),
+                white_ratings: parse_rating_list(record.get(4), player_ids.len())?,
+                black_ratings: parse_rating_list(record.get(5), player_ids.len())?,
+                player_ids,
+            }));
+            continue;
+        }
+        if record[1].is_empty() {
+            let rating_field = |i: usize| match parse_optional_rating(record.get(i)) {
+                Ok(Some(rating)) => Ok(rating),
+                _ => Err("malformed history record."),
+            };
+            history.push(HistoryRecord::Adjustment(Adjustment {
+                player_id: record[0].to_string(),
+                delta: record[2].parse().map_err(|_| "malformed history record.")?,
+                timestamp: record[3].to_string(),
+                white_rating: rating_field(4)?,
+                black_rating: rating_field(5)?,
+            }));
+            continue;
+        }
+        history.push(HistoryRecord::Game(HistoryEntry {
+            white_player_id: record[0].to_string(),
+            black_player_id: record[1].to_string(),
+            result: MatchResult::parse(&record[2])?,
+            timestamp: record[3].to_string(),
+            white_rating: parse_optional_rating(record.get(4))?,
+            black_rating: parse_optional_rating(record.get(5))?,
+            white_rd: parse_optional_rating(record.get(6))?,
+            black_rd: parse_optional_rating(record.get(7))?,
+            expected_score: parse_optional_rating(record.get(8))?,
+        }));
+    }
+
+    Ok(history)
+}
+
+// Whether a history record is timestamped at or after `since`. Records whose timestamp can't
+// be parsed are left out.
+fn recorded_since(record: &HistoryRecord, since: u64) -> bool {
+    parse_timestamp(record_timestamp(record)).is_some_and(|time| time >= since)
+}
+
+// The page of `records` that ends `offset` records before the most recent one and holds at most
+// `limit` of them, still oldest first.
+fn history_page(
+    mut records: Vec<HistoryRecord>,
+    limit: Option<usize>,
+    offset: usize,
+) -> Vec<HistoryRecord> {
+    records.truncate(records.len().saturating_sub(offset));
+    let start = limit.map_or(0, |limit| records.len().saturating_sub(limit));
+    records.split_off(start)
+}
+
+// Parses the comma-separated ratings of the players of a team or free-for-all game or a decay,
+// which must have one for each of its `len` players.
+fn parse_rating_list(field: Option<&str>, len: usize) -> Result<Vec<f64>, Box<dyn Error>> {
+    let ratings = field
+        .unwrap_or_default()
+        .split(',')
+        .map(|rating| rating.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "malformed history record.")?;
+    if ratings.len() != len {
+        return Err("malformed history record.".into());
+    }
+
+    Ok(ratings)
+}
+
+fn parse_optional_rating(field: Option<&str>) -> Result<Option<f64>, Box<dyn Error>> {
+    match field {
+        Some(rat) if !rat.is_empty() => Ok(Some(rat.parse()?)),
+        _ => Ok(None),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Current UTC time as an ISO-8601 timestamp, e.g. 2022-07-05T13:45:00Z.
+fn timestamp() -> String {
+    format_timestamp(now())
+}
+
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // Convert days since the epoch to a civil date (Howard Hinnant's days_from_civil, inverted).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// Parses an ISO-8601 date (2022-07-05) or timestamp (2022-07-05T13:45:00Z) into seconds since
+// the epoch.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = match timestamp.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (timestamp, None),
+    };
+
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let time = match time {
+        Some(time) => {
+            let mut parts = time.strip_suffix('Z')?.splitn(3, ':');
+            let hours: u64 = parts.next()?.parse().ok()?;
+            let minutes: u64 = parts.next()?.parse().ok()?;
+            let seconds: u64 = parts.next()?.parse().ok()?;
+            hours * 3600 + minutes * 60 + seconds
+        }
+        None => 0,
+    };
+
+    // Howard Hinnant's days_from_civil.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    if days < 0 {
+        return None;
+    }
+
+    Some(days as u64 * 86400 + time)
+}
+
+// A not-found error for `player_id` that suggests the closest existing id, so that a typo
+// in a long handle is easy to spot. The suggestion is never applied automatically.
+fn player_not_found(
+    role: &str,
+    player_id: &str,
+    data: &HashMap<String, PlayerRecord>,
+) -> Box<dyn Error> {
+    let message = format!("{} {} not found.", role, player_id);
+    match closest_player_id(player_id, data) {
+        Some(suggestion) => not_found(format!("{} Did you mean '{}'?", message, suggestion)),
+        None => not_found(message),
+    }
+}
+
+// The existing id with the smallest edit distance to `player_id`, if it is close enough to
+// be a likely typo: at most 2 edits, or a third of the id's length for longer ids. Ties go
+// to the alphabetically first id.
+fn closest_player_id<'a>(
+    player_id: &str,
+    data: &'a HashMap<String, PlayerRecord>,
+) -> Option<&'a str> {
+    let limit = cmp::max(2, player_id.chars().count() / 3);
+    (*data)
+        .keys()
+        .map(|candidate| (edit_distance(player_id, candidate), candidate.as_str()))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// Levenshtein distance between two strings, counted in chars.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(cmp::min(
+                substitution,
+                cmp::min(previous[j + 1], current[j]) + 1,
+            ));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn get_rating(
+    player_id: &str,
+    data: &HashMap<String, PlayerRecord>,
+) -> Result<f64, Box<dyn Error>> {
+    match (*data).get(player_id) {
+        Some(player) => Ok(player.rating()),
+        None => Err(player_not_found("player", player_id, data)),
+    }
+}
+
+// Unparseable (NaN) ratings from a corrupted table sort last in either rating order instead of
+// panicking.
+fn sort_standings(
+    data: &HashMap<String, PlayerRecord>,
+    order: SortOrder,
+) -> Vec<(&String, &PlayerRecord)> {
+    let mut data_vec: Vec<(&String, &PlayerRecord)> = data.iter().collect();
+    data_vec.sort_by(|a, b| {
+        let (a_rating, b_rating) = (a.1.rating(), b.1.rating());
+        let nan_last = a_rating.is_nan().cmp(&b_rating.is_nan());
+        match order {
+            SortOrder::RatingDesc => {
+                nan_last.then_with(|| b_rating.partial_cmp(&a_rating).unwrap_or(Ordering::Equal))
+            }
+            SortOrder::RatingAsc => {
+                nan_last.then_with(|| a_rating.partial_cmp(&b_rating).unwrap_or(Ordering::Equal))
+            }
+            SortOrder::Name => a.0.to_lowercase().cmp(&b.0.to_lowercase()),
+            SortOrder::Games => {
+                b.1.games
+                    .cmp(&a.1.games)
+                    .then_with(|| a.0.to_lowercase().cmp(&b.0.to_lowercase()))
+            }
+        }
+    });
+    data_vec
+}
+
+// Formats a rating for text output. Whole ratings are rounded half away from zero rather than
+// with the float formatter's round-half-to-even. Ratings are not clamped at zero, since only
+// the differences between them matter, so they are formatted signed: a long losing streak
+// can take a player below zero unless --floor is given.
+fn format_rating(rating: f64, precision: usize) -> String {
+    if precision == 0 {
+        (rating.round() as i64).to_string()
+    } else {
+        format!("{:.*}", precision, rating)
+    }
+}
+
+const ANSI_RESET: &str = "\x1b[0m";
+
+// Gold (bold yellow), silver (bold white) and bronze (plain yellow) for the top three ranks,
+// using the basic ANSI colors that every color terminal supports. Tied players share a color.
+fn rank_color(rank: usize) -> Option<&'static str> {
+    match rank {
+        1 => Some("\x1b[1;33m"),
+        2 => Some("\x1b[1;37m"),
+        3 => Some("\x1b[33m"),
+        _ => None,
+    }
+}
+
+// The label of the class that `rating` falls in. A NaN rating reaches no class and gets "-".
+fn rating_class(rating: f64) -> &'static str {
+    CLASSES
+        .iter()
+        .find(|(lowest, _)| rating >= *lowest)
+        .map_or("-", |(_, label)| label)
+}
+
+// Columns are padded with spaces to the widest entry rather than separated by tabs, so they
+// line up whatever the terminal's tab stops and however long the player ids are.
+fn write_standings_text<W: io::Write>(
+    data_vec: &[(&String, &PlayerRecord)],
+    options: &ViewOptions,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let ranks = standings_ranks(data_vec);
+    let marker = |player: &PlayerRecord| {
+        if player.games < UNCERTAIN_GAMES {
+            "?"
+        } else {
+            ""
+        }
+    };
+    let rows: Vec<Vec<String>> = data_vec
+        .iter()
+        .map(|(player_id, player)| {
+            let mut row = vec![
+                format!("{}{}", player_id, marker(player)),
+                format_rating(player.white_rating, options.precision),
+                format_rating(player.black_rating, options.precision),
+            ];
+            if options.combined {
+                row.push(format_rating(player.rating(), options.precision));
+            }
+            if options.classes {
+                row.push(rating_class(player.rating()).to_string());
+            }
+            if options.records {
+                row.push(format!(
+                    "+{} -{} ={}",
+                    player.wins, player.losses, player.draws
+                ));
+                row.push(
+                    player
+                        .last_played
+                        .clone()
+                        .unwrap_or_else(|| "-".to_string()),
+                );
+            }
+            row
+        })
+        .collect();
+    let rating_columns = if options.combined { 3 } else { 2 };
+    let column_count = rows.first().map_or(0, |row| row.len());
+    let widths: Vec<usize> = (0..column_count)
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let rank_width = data_vec.len().to_string().len();
+
+    for (rank, row) in ranks.into_iter().zip(&rows) {
+        let mut line = String::new();
+        if options.rank {
+            line.push_str(&format!("{:>1$} ", rank, rank_width));
+        }
+        line.push_str(&format!("{:<1$}", row[0], widths[0]));
+        // Ratings are right-aligned so that their digits line up.
+        for (i, (value, width)) in row.iter().zip(&widths).enumerate().skip(1) {
+            if i <= rating_columns {
+                line.push_str(&format!("  {:>1$}", value, width));
+            } else {
+                line.push_str(&format!("  {:<1$}", value, width));
+            }
+        }
+        match rank_color(rank) {
+            Some(color) if options.color => {
+                writeln!(writer, "{}{}{}", color, line.trim_end(), ANSI_RESET)?
+            }
+            _ => writeln!(writer, "{}", line.trim_end())?,
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// The lowest combined rating that would put `player_id` at `rank` or better, or None if any
+// rating would. Tied players share a rank, so matching the rating of whoever would otherwise be
+// the last player above is enough.
+fn rating_for_rank(
+    player_id: &str,
+    rank: usize,
+    data: &HashMap<String, PlayerRecord>,
+) -> Option<f64> {
+    let mut others: Vec<f64> = data
+        .iter()
+        .filter(|(id, _)| id.as_str() != player_id)
+        .map(|(_, player)| player.rating())
+        .collect();
+    others.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+    others.get(rank - 1).copied()
+}
+
+// Standard competition ranking by combined rating: tied players share a rank and the
+// next rank is skipped, e.g. 1, 2, 2, 4. Ranks follow rating whatever the sort order.
+fn standings_ranks(data_vec: &[(&String, &PlayerRecord)]) -> Vec<usize> {
+    data_vec
+        .iter()
+        .map(|(_, player)| {
+            let rating = player.rating();
+            let ahead = data_vec
+                .iter()
+                .filter(|(_, other)| {
+                    let other = other.rating();
+                    other > rating || (rating.is_nan() && !other.is_nan())
+                })
+                .count();
+            ahead + 1
+        })
+        .collect()
+}
+
+fn standings_json(data_vec: &[(&String, &PlayerRecord)]) -> serde_json::Value {
+    let players: Vec<serde_json::Value> = data_vec
+        .iter()
+        .map(|(player_id, player)| {
+            json!({
+                "player_id": player_id,
+                "rating": player.rating(),
+                "white_rating": player.white_rating,
+                "black_rating": player.black_rating,
+            })
+        })
+        .collect();
+    serde_json::Value::Array(players)
+}
+
+// Ids are stored unquoted in CSV, so characters that would break the format are rejected.
+fn validate_player_id(player_id: &str) -> Result<(), Box<dyn Error>> {
+    if player_id.is_empty() {
+        return Err("player_id must not be empty.".into());
+    }
+    if let Some(c) = player_id
+        .chars()
+        .find(|c| *c == ',' || *c == '"' || c.is_control())
+    {
+        return Err(format!("player_id must not contain {:?}.", c).into());
+    }
+
+    Ok(())
+}
+
+fn write_standings_csv<W: io::Write>(
+    data_vec: &[(&String, &PlayerRecord)],
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = Writer::from_writer(writer);
+
+    wtr.write_record(["Player ID", "Rating"])?;
+    for (player_id, player) in data_vec {
+        wtr.serialize((player_id, player.rating()))?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+// Prints standings in the chosen format. The view options only apply to the text format.
+fn render(
+    format: OutputFormat,
+    data_vec: &[(&String, &PlayerRecord)],
+    options: &ViewOptions,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Text => write_standings_text(data_vec, options, io::stdout()),
+        OutputFormat::Csv => write_standings_csv(data_vec, io::stdout()),
+        OutputFormat::Json => {
+            println!("{}", standings_json(data_vec));
+            Ok(())
+        }
+        OutputFormat::Markdown => write_standings_markdown(data_vec, io::stdout()),
+        OutputFormat::Html => write_standings_html(data_vec, io::stdout()),
+    }
+}
+
+fn render_player_ids<W: io::Write>(
+    format: OutputFormat,
+    player_ids: &[&String],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Text => {
+            for player_id in player_ids {
+                writeln!(writer, "{}", player_id)?;
+            }
+        }
+        OutputFormat::Csv => {
+            let mut wtr = Writer::from_writer(&mut writer);
+            wtr.write_record(["Player ID"])?;
+            for player_id in player_ids {
+                wtr.write_record([player_id])?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Json => writeln!(writer, "{}", json!(player_ids))?,
+        OutputFormat::Markdown => {
+            writeln!(writer, "| Player ID |")?;
+            writeln!(writer, "| --- |")?;
+            for player_id in player_ids {
+                writeln!(writer, "| {} |", player_id.replace('|', "\\|"))?;
+            }
+        }
+        OutputFormat::Html => {
+            let rows: Vec<Vec<String>> = player_ids.iter().map(|id| vec![id.to_string()]).collect();
+            write_html_page("Players", &["Player ID"], &rows, &mut writer)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn render_stats<W: io::Write>(
+    format: OutputFormat,
+    stats: &TableStats,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let rows = [
+        ("Players", stats.players.to_string()),
+        ("Mean", (stats.mean.round() as i64).to_string()),
+        ("Median", (stats.median.round() as i64).to_string()),
+        ("Std. dev.", (stats.std_dev.round() as i64).to_string()),
+        (
+            "Highest",
+            format!("{} ({})", stats.highest.0, stats.highest.1.round() as i64),
+        ),
+        (
+            "Lowest",
+            format!("{} ({})", stats.lowest.0, stats.lowest.1.round() as i64),
+        ),
+    ];
+    match format {
+        OutputFormat::Text => {
+            for (name, value) in &rows {
+                // Pad to the next tab stop after the longest name, "Std. dev.:".
+                let tabs = if name.len() + 1 < 8 { "\t\t" } else { "\t" };
+                writeln!(writer, "{}:{}{}", name, tabs, value)?;
+            }
+        }
+        OutputFormat::Csv => {
+            let mut wtr = Writer::from_writer(&mut writer);
+            wtr.write_record(["Statistic", "Value"])?;
+            for (name, value) in &rows {
+                wtr.write_record([name, value.as_str()])?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Markdown => {
+            writeln!(writer, "| Statistic | Value |")?;
+            writeln!(writer, "| --- | --- |")?;
+            for (name, value) in &rows {
+                writeln!(writer, "| {} | {} |", name, value.replace('|', "\\|"))?;
+            }
+        }
+        OutputFormat::Html => {
+            let rows: Vec<Vec<String>> = rows
+                .iter()
+                .map(|(name, value)| vec![name.to_string(), value.clone()])
+                .collect();
+            write_html_page("Statistics", &["Statistic", "Value"], &rows, &mut writer)?;
+        }
+        // JSON keeps full precision rather than the rounded display values.
+        OutputFormat::Json => {
+            let value = json!({
+                "players": stats.players,
+                "mean": stats.mean,
+                "median": stats.median,
+                "std_dev": stats.std_dev,
+                "highest": {"player_id": stats.highest.0, "rating": stats.highest.1},
+                "lowest": {"player_id": stats.lowest.0, "rating": stats.lowest.1},
+            });
+            writeln!(writer, "{}", value)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_standings_html<W: io::Write>(
+    data_vec: &[(&String, &PlayerRecord)],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<Vec<String>> = data_vec
+        .iter()
+        .zip(standings_ranks(data_vec))
+        .map(|((player_id, player), rank)| {
+            vec![
+                rank.to_string(),
+                player_id.to_string(),
+                format_rating(player.rating(), 0),
+            ]
+        })
+        .collect();
+    write_html_page(
+        "Standings",
+        &["Rank", "Player ID", "Rating"],
+        &rows,
+        &mut writer,
+    )?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.4em 1em; text-align: left; border-bottom: 1px solid #ddd; }
+th { background: #f4f4f4; }
+tbody tr:nth-child(even) { background: #fafafa; }";
+
+// Writes a standalone HTML page holding one table, styled inline so that it needs nothing else
+// alongside it.
+fn write_html_page<W: io::Write>(
+    title: &str,
+    headers: &[&str],
+    rows: &[Vec<String>],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let cells = |tag: &str, values: &mut dyn Iterator<Item = &str>| -> String {
+        values
+            .map(|value| format!("<{0}>{1}</{0}>", tag, html_escape(value)))
+            .collect()
+    };
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html lang=\"en\">")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>{}</title>", html_escape(title))?;
+    writeln!(writer, "<style>\n{}\n</style>", HTML_STYLE)?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "<table>")?;
+    writeln!(writer, "<thead>")?;
+    writeln!(
+        writer,
+        "<tr>{}</tr>",
+        cells("th", &mut headers.iter().copied())
+    )?;
+    writeln!(writer, "</thead>")?;
+    writeln!(writer, "<tbody>")?;
+    for row in rows {
+        writeln!(
+            writer,
+            "<tr>{}</tr>",
+            cells("td", &mut row.iter().map(String::as_str))
+        )?;
+    }
+    writeln!(writer, "</tbody>")?;
+    writeln!(writer, "</table>")?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+
+    Ok(())
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Writes what `show` prints: one "field<TAB>value" pair per line, in a fixed order, for easy
+// use in scripts.
+fn write_player_details<W: io::Write>(
+    player_id: &str,
+    player: &PlayerRecord,
+    precision: usize,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "Player ID\t{}", player_id)?;
+    writeln!(
+        writer,
+        "Rating\t{}",
+        format_rating(player.rating(), precision)
+    )?;
+    writeln!(
+        writer,
+        "White Rating\t{}",
+        format_rating(player.white_rating, precision)
+    )?;
+    writeln!(
+        writer,
+        "Black Rating\t{}",
+        format_rating(player.black_rating, precision)
+    )?;
+    writeln!(writer, "RD\t{}", player.rd.round() as i64)?;
+    writeln!(writer, "Games\t{}", player.games)?;
+    writeln!(writer, "Wins\t{}", player.wins)?;
+    writeln!(writer, "Losses\t{}", player.losses)?;
+    writeln!(writer, "Draws\t{}", player.draws)?;
+    writeln!(
+        writer,
+        "Last Played\t{}",
+        player.last_played.as_deref().unwrap_or("-")
+    )?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn write_standings_markdown<W: io::Write>(
+    data_vec: &[(&String, &PlayerRecord)],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "| Player ID | Rating |")?;
+    writeln!(writer, "| --- | --- |")?;
+    for (player_id, player) in data_vec {
+        // An unescaped pipe would end the cell early.
+        writeln!(
+            writer,
+            "| {} | {} |",
+            player_id.replace('|', "\\|"),
+            format_rating(player.rating(), 0)
+        )?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn create_player(
+    player_id: String,
+    rating: f64,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    validate_player_id(&player_id)?;
+    match (*data).entry(player_id) {
+        Entry::Occupied(_) => return Err("player_id already in use.".into()),
+        Entry::Vacant(v) => {
+            v.insert(PlayerRecord::new(rating));
+        }
+    }
+
+    Ok(())
+}
+
+// Reads a newline-separated list of player ids, ignoring blank lines.
+fn read_player_ids(filename: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let contents = fs::read_to_string(filename)?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+// Creates every player that doesn't exist yet, returning the ids that were skipped.
+// All ids are validated first so that a bad id doesn't leave the table half-populated.
+fn create_players(
+    player_ids: Vec<String>,
+    rating: f64,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    for player_id in &player_ids {
+        validate_player_id(player_id)?;
+    }
+    let mut skipped = Vec::new();
+    for player_id in player_ids {
+        match (*data).entry(player_id) {
+            Entry::Occupied(o) => skipped.push(o.key().clone()),
+            Entry::Vacant(v) => {
+                v.insert(PlayerRecord::new(rating));
+            }
+        }
+    }
+
+    Ok(skipped)
+}
+
+fn remove_player(
+    player_id: String,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    match (*data).remove(&player_id) {
+        Some(_) => Ok(()),
+        None => Err(not_found("player_id not found.".to_string())),
+    }
+}
+
+// Puts a player's rating back to INITIAL_RATING, optionally clearing their games and record too.
+fn reset_player(
+    player_id: String,
+    clear_games: bool,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    let player = match (*data).get_mut(&player_id) {
+        Some(player) => player,
+        None => return Err(not_found("player_id not found.".to_string())),
+    };
+    let reset = PlayerRecord::new(INITIAL_RATING);
+    if clear_games {
+        *player = reset;
+    } else {
+        player.white_rating = reset.white_rating;
+        player.black_rating = reset.black_rating;
+        player.rd = reset.rd;
+    }
+
+    Ok(())
+}
+
+// Adds a signed `delta` to both of a player's ratings, returning the adjustment to log.
+fn adjust_player(
+    player_id: String,
+    delta: f64,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<Adjustment, Box<dyn Error>> {
+    let player = match (*data).get_mut(&player_id) {
+        Some(player) => player,
+        None => return Err(not_found("player_id not found.".to_string())),
+    };
+    let adjustment = Adjustment {
+        player_id,
+        delta,
+        timestamp: timestamp(),
+        white_rating: player.white_rating,
+        black_rating: player.black_rating,
+    };
+    player.white_rating += delta;
+    player.black_rating += delta;
+
+    Ok(adjustment)
+}
+
+// Removes every player rated below `below`, returning their ids in alphabetical order.
+fn prune_players(below: f64, data: &mut HashMap<String, PlayerRecord>) -> Vec<String> {
+    let mut pruned: Vec<String> = (*data)
+        .iter()
+        .filter(|(_, player)| player.rating() < below)
+        .map(|(player_id, _)| player_id.clone())
+        .collect();
+    pruned.sort();
+    for player_id in &pruned {
+        (*data).remove(player_id);
+    }
+    pruned
+}
+
+fn rename_player(
+    old_id: String,
+    new_id: String,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    validate_player_id(&new_id)?;
+    if (*data).contains_key(&new_id) {
+        return Err("new player_id already in use.".into());
+    }
+    let player = match (*data).remove(&old_id) {
+        Some(player) => player,
+        None => return Err(not_found("player_id not found.".to_string())),
+    };
+    (*data).insert(new_id, player);
+
+    Ok(())
+}
+
+// Renames `old_id` to `new_id` wherever it appears in `history`, so that the player's games
+// and adjustments still count for them after `rename`.
+fn rename_in_history(history: &mut [HistoryRecord], old_id: &str, new_id: &str) {
+    let rename = |player_id: &mut String| {
+        if player_id == old_id {
+            *player_id = new_id.to_string();
+        }
+    };
+    for record in history {
+        match record {
+            HistoryRecord::Game(entry) => {
+                rename(&mut entry.white_player_id);
+                rename(&mut entry.black_player_id);
+            }
+            HistoryRecord::Adjustment(adjustment) => rename(&mut adjustment.player_id),
+            HistoryRecord::TeamGame(game) => {
+                game.white_team.iter_mut().for_each(rename);
+                game.black_team.iter_mut().for_each(rename);
+            }
+            HistoryRecord::FreeForAll(game) => game.ranking.iter_mut().for_each(rename),
+            HistoryRecord::Decay(decay) => decay.player_ids.iter_mut().for_each(rename),
+        }
+    }
+}
+
+/// Expected score of a player rated `rating_a` against a player rated `rating_b`, between 0 and 1.
+pub fn expected_score(rating_a: f64, rating_b: f64) -> f64 {
+    expected_score_with_scale(rating_a, rating_b, RATING_CONST)
+}
+
+/// Like [`expected_score`], but with `scale` in place of the default rating scale.
+pub fn expected_score_with_scale(rating_a: f64, rating_b: f64, scale: f64) -> f64 {
+    1. / (f64::exp(-(rating_a - rating_b) / scale) + 1.)
+}
+
+/// New (white, black) ratings after a game between two established players.
+pub fn new_ratings(white: f64, black: f64, result: MatchResult) -> (f64, f64) {
+    new_ratings_with_k(white, black, result, K, K)
+}
+
+/// New (white, black) ratings after a game, with a separate K-factor for each side.
+pub fn new_ratings_with_k(
+    white: f64,
+    black: f64,
+    result: MatchResult,
+    white_k: f64,
+    black_k: f64,
+) -> (f64, f64) {
+    new_ratings_with_scale(white, black, result, white_k, black_k, RATING_CONST)
+}
+
+fn new_ratings_with_scale(
+    white: f64,
+    black: f64,
+    result: MatchResult,
+    white_k: f64,
+    black_k: f64,
+    scale: f64,
+) -> (f64, f64) {
+    let white_score_expected = expected_score_with_scale(white, black, scale);
+    let black_score_expected = 1. - white_score_expected;
+
+    let (white_score, black_score) = result.scores();
+
+    let white_rating_change = white_k * (white_score - white_score_expected);
+    let black_rating_change = black_k * (black_score - black_score_expected);
+
+    (white + white_rating_change, black + black_rating_change)
+}
+
+fn margin_multiplier(margin: f64) -> f64 {
+    1. + MARGIN_SCALE * (1. + margin).ln()
+}
+
+fn k_factor(games: u32) -> f64 {
+    if games < PROVISIONAL_GAMES {
+        PROVISIONAL_K
+    } else {
+        K
+    }
+}
+
+fn update_ratings(
+    white_player_id: String,
+    black_player_id: String,
+    result: MatchResult,
+    options: &RatingOptions,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<RatingChange, Box<dyn Error>> {
+    // An explicit K overrides the per-player (provisional or established) K-factor.
+    let player_k = |games| options.k.unwrap_or_else(|| k_factor(games));
+    let (white_rating, white_k) = match (*data).get(&white_player_id) {
+        Some(player) => (player.white_rating, player_k(player.games)),
+        None => return Err(player_not_found("white player", &white_player_id, data)),
+    };
+    let (black_rating, black_k) = match (*data).get(&black_player_id) {
+        Some(player) => (player.black_rating, player_k(player.games)),
+        None => return Err(player_not_found("black player", &black_player_id, data)),
+    };
+    let multiplier =
+        options.k_multiplier() * options.upset_multiplier(result, white_rating, black_rating);
+    let (white_k, black_k) = (white_k * multiplier, black_k * multiplier);
+    let (white_new_rating, black_new_rating) = new_ratings_with_scale(
+        white_rating,
+        black_rating,
+        result,
+        white_k,
+        black_k,
+        options.scale(),
+    );
+    let (white_new_rating, black_new_rating) =
+        if options.skips_draw(result, white_rating, black_rating) {
+            (white_rating, black_rating)
+        } else {
+            (
+                options.clamp(options.cap(white_rating, white_new_rating)),
+                options.clamp(options.cap(black_rating, black_new_rating)),
+            )
+        };
+    let (white_score, black_score) = result.scores();
+
+    if let Some(white) = (*data).get_mut(&white_player_id) {
+        white.white_rating = white_new_rating;
+        white.add_game(white_score);
+    }
+    if let Some(black) = (*data).get_mut(&black_player_id) {
+        black.black_rating = black_new_rating;
+        black.add_game(black_score);
+    }
+
+    Ok(RatingChange {
+        white_before: white_rating,
+        white_after: white_new_rating,
+        black_before: black_rating,
+        black_after: black_new_rating,
+    })
+}
+
+// Rates a free-for-all game from its finishing order, winner first. The game is scored as a
+// win for each player over everyone who finished behind them, and each player's K is spread
+// over their N - 1 opponents so that one game moves a rating about as far as a two-player
+// game would. Colors don't apply, so both of a player's ratings move by the same amount and
+// the expected scores use combined ratings. The game counts as a win for the winner, a loss
+// for the last player and, in between, as a win or loss by whether the player beat more or
+// fewer than half of the field.
+fn update_ffa_ratings(
+    ranking: &[String],
+    options: &RatingOptions,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<Vec<PlayerChange>, Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    for player_id in ranking {
+        if !seen.insert(player_id) {
+            return Err(format!("player {} appears more than once in the game.", player_id).into());
+        }
+        if !(*data).contains_key(player_id) {
+            return Err(not_found(format!("player {} not found.", player_id)));
+        }
+    }
+
+    let ratings: Vec<f64> = ranking.iter().map(|id| (*data)[id].rating()).collect();
+    let opponents = (ranking.len() - 1) as f64;
+    let multiplier = options.k_multiplier();
+    let mut changes = Vec::new();
+    for (i, player_id) in ranking.iter().enumerate() {
+        let mut surprise = 0.;
+        for (j, opponent_rating) in ratings.iter().enumerate() {
+            if i != j {
+                let score = if i < j { 1. } else { 0. };
+                surprise += score
+                    - expected_score_with_scale(ratings[i], *opponent_rating, options.scale());
+            }
+        }
+        let player = (*data).get_mut(player_id).unwrap();
+        let k = options.k.unwrap_or_else(|| k_factor(player.games)) * multiplier;
+        let before = ratings[i];
+        let after = options.clamp(options.cap(before, before + k * surprise / opponents));
+        player.white_rating += after - before;
+        player.black_rating += after - before;
+        player.add_game((ranking.len() - 1 - i) as f64 / opponents);
+        changes.push(PlayerChange {
+            player_id: player_id.clone(),
+            before,
+            after,
+        });
+    }
+
+    Ok(changes)
+}
+
+// Rates a game between two teams. Each player is rated as if they had played a single game
+// against an opponent with the average rating of the other team, using their rating for the
+// color their team played. Returns the change for every player, White first.
+fn update_team_ratings(
+    white_team: &[String],
+    black_team: &[String],
+    result: MatchResult,
+    options: &RatingOptions,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<Vec<PlayerChange>, Box<dyn Error>> {
+    let mut seen = HashSet::new();
+    for player_id in white_team.iter().chain(black_team) {
+        if !seen.insert(player_id) {
+            return Err(format!("player {} appears more than once in the game.", player_id).into());
+        }
+        if !(*data).contains_key(player_id) {
+            return Err(not_found(format!("player {} not found.", player_id)));
+        }
+    }
+
+    let average = |team: &[String], rating: fn(&PlayerRecord) -> f64| {
+        team.iter().map(|id| rating(&(*data)[id])).sum::<f64>() / team.len() as f64
+    };
+    let white_average = average(white_team, |p| p.white_rating);
+    let black_average = average(black_team, |p| p.black_rating);
+
+    let multiplier =
+        options.k_multiplier() * options.upset_multiplier(result, white_average, black_average);
+    let skip = options.skips_draw(result, white_average, black_average);
+    let (white_score, black_score) = result.scores();
+    let mut changes = Vec::new();
+    for (team, opponent_average, score, is_white) in [
+        (white_team, black_average, white_score, true),
+        (black_team, white_average, black_score, false),
+    ] {
+        for player_id in team {
+            let player = (*data).get_mut(player_id).unwrap();
+            let k = options.k.unwrap_or_else(|| k_factor(player.games)) * multiplier;
+            let rating = if is_white {
+                &mut player.white_rating
+            } else {
+                &mut player.black_rating
+            };
+            let before = *rating;
+            if !skip {
+                let after = before
+                    + k * (score
+                        - expected_score_with_scale(before, opponent_average, options.scale()));
+                *rating = options.clamp(options.cap(before, after));
+            }
+            changes.push(PlayerChange {
+                player_id: player_id.clone(),
+                before,
+                after: *rating,
+            });
+            player.add_game(score);
+        }
+    }
+
+    Ok(changes)
+}
+
+struct TableStats {
+    players: usize,
+    mean: f64,
+    median: f64,
+    std_dev: f64,
+    highest: (String, f64),
+    lowest: (String, f64),
+}
+
+// Summarises the combined ratings of every player, or None for an empty table.
+fn table_stats(data: &HashMap<String, PlayerRecord>) -> Option<TableStats> {
+    let data_vec = sort_standings(data, SortOrder::RatingDesc);
+    let (highest, lowest) = match (data_vec.first(), data_vec.last()) {
+        (Some(highest), Some(lowest)) => (highest, lowest),
+        _ => return None,
+    };
+    let ratings: Vec<f64> = data_vec.iter().map(|(_, player)| player.rating()).collect();
+    let n = ratings.len();
+
+    let mean = ratings.iter().sum::<f64>() / n as f64;
+    let median = if n % 2 == 1 {
+        ratings[n / 2]
+    } else {
+        (ratings[n / 2 - 1] + ratings[n / 2]) / 2.
+    };
+    let variance = ratings.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n as f64;
+
+    Some(TableStats {
+        players: n,
+        mean,
+        median,
+        std_dev: variance.sqrt(),
+        highest: (highest.0.clone(), highest.1.rating()),
+        lowest: (lowest.0.clone(), lowest.1.rating()),
+    })
+}
+
+// A small seedable pseudo-random number generator (SplitMix64), good enough for simulations.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in 0..n.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_f64() * n as f64) as usize
+    }
+
+    // Standard normal, by the Box-Muller transform.
+    fn normal(&mut self) -> f64 {
+        let u = 1. - self.next_f64();
+        let v = self.next_f64();
+        (-2. * u.ln()).sqrt() * (2. * f64::consts::PI * v).cos()
+    }
+}
+
+// The spread of the hidden true strengths in `leelo simulate`.
+const SIMULATION_STRENGTH_SD: f64 = 200.;
+
+// How closely the ratings track the true strengths after `games` simulated games.
+struct Checkpoint {
+    games: usize,
+    rms_error: f64,
+    correlation: f64,
+}
+
+// Gives `players` synthetic players a hidden true strength, then plays `games` random games
+// between them, each won by White with the expected score of White's true strength as the
+// probability. Everyone starts at INITIAL_RATING and is rated as `leelo game` would. Reports
+// ten evenly spaced checkpoints. Only differences between ratings are meaningful, so the error
+// is measured after shifting the ratings to the mean true strength.
+fn simulate(players: usize, games: usize, seed: u64) -> Vec<Checkpoint> {
+    let mut rng = Rng::new(seed);
+    let strengths: Vec<f64> = (0..players)
+        .map(|_| INITIAL_RATING + SIMULATION_STRENGTH_SD * rng.normal())
+        .collect();
+    let player_ids: Vec<String> = (1..=players).map(|i| format!("p{}", i)).collect();
+    let mut data: HashMap<String, PlayerRecord> = player_ids
+        .iter()
+        .map(|id| (id.clone(), PlayerRecord::new(INITIAL_RATING)))
+        .collect();
+
+    let mut checkpoints = Vec::new();
+    for game in 1..=games {
+        let white = rng.below(players);
+        let black = (white + 1 + rng.below(players - 1)) % players;
+        let result = if rng.next_f64() < expected_score(strengths[white], strengths[black]) {
+            MatchResult::WhiteWin
+        } else {
+            MatchResult::BlackWin
+        };
+        // Both players exist and are distinct, so this can't fail.
+        let _ = update_ratings(
+            player_ids[white].clone(),
+            player_ids[black].clone(),
+            result,
+            &RatingOptions::default(),
+            &mut data,
+        );
+
+        if game * 10 / games != (game - 1) * 10 / games {
+            let ratings: Vec<f64> = player_ids.iter().map(|id| data[id].rating()).collect();
+            checkpoints.push(Checkpoint {
+                games: game,
+                rms_error: rms_error(&ratings, &strengths),
+                correlation: correlation(&ratings, &strengths),
+            });
+        }
+    }
+    checkpoints
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn rms_error(ratings: &[f64], strengths: &[f64]) -> f64 {
+    let offset = mean(strengths) - mean(ratings);
+    let squares: Vec<f64> = ratings
+        .iter()
+        .zip(strengths)
+        .map(|(rating, strength)| (rating + offset - strength).powi(2))
+        .collect();
+    mean(&squares).sqrt()
+}
+
+// Pearson correlation, or 0 when either side has no spread.
+fn correlation(xs: &[f64], ys: &[f64]) -> f64 {
+    let (x_mean, y_mean) = (mean(xs), mean(ys));
+    let mut covariance = 0.;
+    let (mut x_variance, mut y_variance) = (0., 0.);
+    for (x, y) in xs.iter().zip(ys) {
+        covariance += (x - x_mean) * (y - y_mean);
+        x_variance += (x - x_mean).powi(2);
+        y_variance += (y - y_mean).powi(2);
+    }
+    if x_variance == 0. || y_variance == 0. {
+        return 0.;
+    }
+    covariance / (x_variance * y_variance).sqrt()
+}
+
+// Schedules every player against every other exactly once using the circle method:
+// the first player stays put while the rest rotate around them. With an odd number of
+// players a bye is added, and whoever is paired with it sits the round out. Pairings
+// are (white, black), with the fixed player's colour alternating between rounds.
+fn round_robin<'a>(player_ids: &[&'a str]) -> Vec<Vec<(&'a str, Option<&'a str>)>> {
+    let mut circle: Vec<Option<&str>> = player_ids.iter().map(|id| Some(*id)).collect();
+    if circle.len() % 2 == 1 {
+        circle.push(None);
+    }
+    let n = circle.len();
+
+    let mut rounds = Vec::new();
+    for round in 0..n.saturating_sub(1) {
+        let mut pairings = Vec::new();
+        for i in 0..n / 2 {
+            let (mut white, mut black) = (circle[i], circle[n - 1 - i]);
+            if i == 0 && round % 2 == 1 {
+                (white, black) = (black, white);
+            }
+            match (white, black) {
+                (Some(white), black) => pairings.push((white, black)),
+                (None, Some(black)) => pairings.push((black, None)),
+                (None, None) => {}
+            }
+        }
+        rounds.push(pairings);
+        circle[1..].rotate_right(1);
+    }
+    rounds
+}
+
+// Each player's expected points if every player meets every other once, from their combined
+// ratings, highest first. Players on the same expected points are ordered by id.
+fn expected_round_robin<'a>(players: &[(&'a str, f64)], scale: f64) -> Vec<(&'a str, f64)> {
+    let mut standings: Vec<(&str, f64)> = players
+        .iter()
+        .map(|&(player_id, rating)| {
+            let points = players
+                .iter()
+                .filter(|(other_id, _)| *other_id != player_id)
+                .map(|&(_, other)| expected_score_with_scale(rating, other, scale))
+                .sum();
+            (player_id, points)
+        })
+        .collect();
+    standings.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.0.cmp(b.0))
+    });
+    standings
+}
+
+// Pairs the next Swiss round. Players are ranked by their score in the history, then by
+// their position in `standings`. Each player is paired with the highest-ranked opponent
+// they haven't met yet, backtracking when that leaves someone without a fresh opponent;
+// rematches are only allowed when no pairing avoids them. With an odd number of players
+// the lowest-ranked one gets the bye. Whoever has had White less often gets White.
+fn swiss_pairings<'a>(
+    standings: &[&'a str],
+    history: &[HistoryEntry],
+) -> Vec<(&'a str, Option<&'a str>)> {
+    let mut scores: HashMap<&str, f64> = HashMap::new();
+    let mut whites: HashMap<&str, i32> = HashMap::new();
+    let mut met: HashSet<(&str, &str)> = HashSet::new();
+    for entry in history {
+        let (white, black) = (
+            entry.white_player_id.as_str(),
+            entry.black_player_id.as_str(),
+        );
+        let (white_score, black_score) = entry.result.scores();
+        *scores.entry(white).or_insert(0.) += white_score;
+        *scores.entry(black).or_insert(0.) += black_score;
+        *whites.entry(white).or_insert(0) += 1;
+        *whites.entry(black).or_insert(0) -= 1;
+        met.insert((white, black));
+        met.insert((black, white));
+    }
+
+    let mut ranked = standings.to_vec();
+    let score = |player_id: &str| scores.get(player_id).copied().unwrap_or(0.);
+    // A stable sort keeps the standings order among players on the same score.
+    ranked.sort_by(|a, b| score(b).partial_cmp(&score(a)).unwrap_or(Ordering::Equal));
+
+    let bye = if ranked.len() % 2 == 1 {
+        ranked.pop()
+    } else {
+        None
+    };
+    let pairs = pair_without_rematches(&ranked, &met)
+        .unwrap_or_else(|| ranked.chunks(2).map(|pair| (pair[0], pair[1])).collect());
+
+    let mut pairings: Vec<(&str, Option<&str>)> = pairs
+        .into_iter()
+        .map(|(a, b)| {
+            let balance = |player_id: &str| whites.get(player_id).copied().unwrap_or(0);
+            if balance(b) < balance(a) {
+                (b, Some(a))
+            } else {
+                (a, Some(b))
+            }
+        })
+        .collect();
+    if let Some(bye) = bye {
+        pairings.push((bye, None));
+    }
+    pairings
+}
+
+fn pair_without_rematches<'a>(
+    ranked: &[&'a str],
+    met: &HashSet<(&str, &str)>,
+) -> Option<Vec<(&'a str, &'a str)>> {
+    let (first, rest) = match ranked.split_first() {
+        Some(split) => split,
+        None => return Some(Vec::new()),
+    };
+    for (i, opponent) in rest.iter().enumerate() {
+        if met.contains(&(*first, *opponent)) {
+            continue;
+        }
+        let mut remaining = rest.to_vec();
+        remaining.remove(i);
+        if let Some(mut pairs) = pair_without_rematches(&remaining, met) {
+            pairs.insert(0, (*first, *opponent));
+            return Some(pairs);
+        }
+    }
+    None
+}
+
+// Returns (wins for a, wins for b, draws) over all recorded games between the two players.
+fn head_to_head(player_a: &str, player_b: &str, history: &[HistoryEntry]) -> (u32, u32, u32) {
+    let (mut a_wins, mut b_wins, mut draws) = (0, 0, 0);
+    for entry in history {
+        let (white, black) = (&entry.white_player_id, &entry.black_player_id);
+        let a_score = if white == player_a && black == player_b {
+            entry.result.scores().0
+        } else if white == player_b && black == player_a {
+            entry.result.scores().1
+        } else {
+            continue;
+        };
+        match a_score {
+            s if s > 0.5 => a_wins += 1,
+            s if s < 0.5 => b_wins += 1,
+            _ => draws += 1,
+        }
+    }
+    (a_wins, b_wins, draws)
+}
+
+// Decays the ratings of players whose last recorded game is older than DECAY_INACTIVE_DAYS,
+// returning the decay for the history. Players with no recorded games are left alone.
+fn decay_ratings(
+    history: &[HistoryRecord],
+    now: u64,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Decay {
+    let mut last_played: HashMap<&str, u64> = HashMap::new();
+    for record in history.iter().filter(|record| record_is_game(record)) {
+        if let Some(time) = parse_timestamp(record_timestamp(record)) {
+            for player_id in record_player_ids(record) {
+                let last = last_played.entry(player_id).or_insert(time);
+                *last = cmp::max(*last, time);
+            }
+        }
+    }
+
+    let mut inactive: Vec<String> = (*data)
+        .iter()
+        .filter(|(player_id, player)| {
+            last_played
+                .get(player_id.as_str())
+                .is_some_and(|&time| now.saturating_sub(time) > DECAY_INACTIVE_DAYS * 86400)
+                && player.rating() > INITIAL_RATING
+        })
+        .map(|(player_id, _)| player_id.clone())
+        .collect();
+    inactive.sort();
+    decay_players(inactive, data)
+}
+
+// Moves each of `player_ids`' ratings that is above the initial rating DECAY_RATE of the way
+// back to it. Players not in the table are left out of the returned decay.
+fn decay_players(player_ids: Vec<String>, data: &mut HashMap<String, PlayerRecord>) -> Decay {
+    let mut decay = Decay {
+        player_ids: Vec::new(),
+        timestamp: timestamp(),
+        white_ratings: Vec::new(),
+        black_ratings: Vec::new(),
+    };
+    for player_id in player_ids {
+        let player = match (*data).get_mut(&player_id) {
+            Some(player) => player,
+            None => continue,
+        };
+        decay.white_ratings.push(player.white_rating);
+        decay.black_ratings.push(player.black_rating);
+        for rating in [&mut player.white_rating, &mut player.black_rating] {
+            if *rating > INITIAL_RATING {
+                *rating -= DECAY_RATE * (*rating - INITIAL_RATING);
+            }
+        }
+        decay.player_ids.push(player_id);
+    }
+    decay
+}
+
+// New (rating, RD) for a player after a single game under Glicko-1, treating the game as its own
+// rating period.
+fn glicko_update(
+    rating: f64,
+    rd: f64,
+    opponent_rating: f64,
+    opponent_rd: f64,
+    score: f64,
+) -> (f64, f64) {
+    let g =
+        1. / (1. + 3. * GLICKO_Q.powi(2) * opponent_rd.powi(2) / f64::consts::PI.powi(2)).sqrt();
+    let expected = 1. / (1. + 10f64.powf(-g * (rating - opponent_rating) / 400.));
+    let d_squared = 1. / (GLICKO_Q.powi(2) * g.powi(2) * expected * (1. - expected));
+    let precision = 1. / rd.powi(2) + 1. / d_squared;
+
+    let new_rating = rating + GLICKO_Q / precision * g * (score - expected);
+    let new_rd = (1. / precision).sqrt();
+    (new_rating, new_rd)
+}
+
+// Glicko counterpart of update_ratings. Both players are updated from their pre-game ratings and RDs.
+fn update_ratings_glicko(
+    white_player_id: String,
+    black_player_id: String,
+    result: MatchResult,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<RatingChange, Box<dyn Error>> {
+    let (white_rating, white_rd) = match (*data).get(&white_player_id) {
+        Some(player) => (player.white_rating, player.rd),
+        None => return Err(player_not_found("white player", &white_player_id, data)),
+    };
+    let (black_rating, black_rd) = match (*data).get(&black_player_id) {
+        Some(player) => (player.black_rating, player.rd),
+        None => return Err(player_not_found("black player", &black_player_id, data)),
+    };
+    let (white_score, black_score) = result.scores();
+    let (white_new_rating, white_new_rd) =
+        glicko_update(white_rating, white_rd, black_rating, black_rd, white_score);
+    let (black_new_rating, black_new_rd) =
+        glicko_update(black_rating, black_rd, white_rating, white_rd, black_score);
+
+    if let Some(white) = (*data).get_mut(&white_player_id) {
+        white.white_rating = white_new_rating;
+        white.rd = white_new_rd;
+        white.add_game(white_score);
+    }
+    if let Some(black) = (*data).get_mut(&black_player_id) {
+        black.black_rating = black_new_rating;
+        black.rd = black_new_rd;
+        black.add_game(black_score);
+    }
+
+    Ok(RatingChange {
+        white_before: white_rating,
+        white_after: white_new_rating,
+        black_before: black_rating,
+        black_after: black_new_rating,
+    })
+}
+
+// Adds every player in `b` to `a`, returning how many players were in both tables. Averaged
+// players get the mean of both ratings and the games from both tables.
+fn merge_tables(
+    a: &mut HashMap<String, PlayerRecord>,
+    b: HashMap<String, PlayerRecord>,
+    on_conflict: ConflictPolicy,
+) -> usize {
+    let mut overlapping = 0;
+    for (player_id, player_b) in b {
+        match a.entry(player_id) {
+            Entry::Vacant(v) => {
+                v.insert(player_b);
+            }
+            Entry::Occupied(mut o) => {
+                overlapping += 1;
+                let player_a = o.get_mut();
+                match on_conflict {
+                    ConflictPolicy::KeepA => {}
+                    ConflictPolicy::KeepB => *player_a = player_b,
+                    ConflictPolicy::Average => {
+                        player_a.white_rating =
+                            (player_a.white_rating + player_b.white_rating) / 2.;
+                        player_a.black_rating =
+                            (player_a.black_rating + player_b.black_rating) / 2.;
+                        player_a.rd = (player_a.rd + player_b.rd) / 2.;
+                        player_a.games += player_b.games;
+                        player_a.wins += player_b.wins;
+                        player_a.losses += player_b.losses;
+                        player_a.draws += player_b.draws;
+                        // ISO-8601 timestamps sort chronologically as strings.
+                        player_a.last_played =
+                            cmp::max(player_a.last_played.take(), player_b.last_played);
+                    }
+                }
+            }
+        }
+    }
+    overlapping
+}
+
+// Resets every player to their starting rating and replays the history in order, returning the
+// history with the replayed pre-game ratings and the number of games skipped because one of
+// the players is no longer in the table. Skipped games are kept in the history unchanged.
+// Manual adjustments and decays are reapplied where they fall in the history. A player's
+// starting rating is the one stored before their first record, so that players created with
+// --initial or --rating keep their seed, and players with no records keep their rating.
+fn rebuild_ratings(
+    history: &[HistoryRecord],
+    options: &RatingOptions,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(Vec<HistoryRecord>, usize), Box<dyn Error>> {
+    let stored = StoredRatings::new(history, data);
+    let seeds: Vec<(String, (f64, f64))> = (*data)
+        .keys()
+        .map(|player_id| (player_id.clone(), stored.after(player_id, None)))
+        .collect();
+    for (player_id, (white_rating, black_rating)) in seeds {
+        let mut player = PlayerRecord::new(INITIAL_RATING);
+        player.white_rating = white_rating;
+        player.black_rating = black_rating;
+        (*data).insert(player_id, player);
+    }
+
+    let mut replayed = Vec::new();
+    let mut skipped = 0;
+    for record in history {
+        let entry = match record {
+            HistoryRecord::Game(entry) => entry,
+            HistoryRecord::TeamGame(game) => {
+                let players: Vec<&String> =
+                    game.white_team.iter().chain(&game.black_team).collect();
+                if !players.iter().all(|id| (*data).contains_key(*id)) {
+                    replayed.push(record.clone());
+                    skipped += 1;
+                    continue;
+                }
+                let (mut new_game, _) = record_team_game(
+                    game.white_team.clone(),
+                    game.black_team.clone(),
+                    game.result,
+                    options,
+                    data,
+                )?;
+                new_game.timestamp = game.timestamp.clone();
+                for player_id in players {
+                    if let Some(player) = (*data).get_mut(player_id) {
+                        player.last_played = Some(game.timestamp.clone());
+                    }
+                }
+                replayed.push(HistoryRecord::TeamGame(new_game));
+                continue;
+            }
+            HistoryRecord::FreeForAll(game) => {
+                if !game.ranking.iter().all(|id| (*data).contains_key(id)) {
+                    replayed.push(record.clone());
+                    skipped += 1;
+                    continue;
+                }
+                let (mut new_game, _) = record_ffa_game(game.ranking.clone(), options, data)?;
+                new_game.timestamp = game.timestamp.clone();
+                for player_id in &game.ranking {
+                    if let Some(player) = (*data).get_mut(player_id) {
+                        player.last_played = Some(game.timestamp.clone());
+                    }
+                }
+                replayed.push(HistoryRecord::FreeForAll(new_game));
+                continue;
+            }
+            HistoryRecord::Decay(decay) => {
+                let mut new_decay = decay_players(decay.player_ids.clone(), data);
+                new_decay.timestamp = decay.timestamp.clone();
+                replayed.push(HistoryRecord::Decay(new_decay));
+                continue;
+            }
+            HistoryRecord::Adjustment(adjustment) => {
+                match adjust_player(adjustment.player_id.clone(), adjustment.delta, data) {
+                    Ok(mut new_adjustment) => {
+                        new_adjustment.timestamp = adjustment.timestamp.clone();
+                        replayed.push(HistoryRecord::Adjustment(new_adjustment));
+                    }
+                    Err(_) => replayed.push(record.clone()),
+                }
+                continue;
+            }
+        };
+        if !(*data).contains_key(&entry.white_player_id)
+            || !(*data).contains_key(&entry.black_player_id)
+        {
+            replayed.push(record.clone());
+            skipped += 1;
+            continue;
+        }
+        let (mut new_entry, _) = record_game(
+            entry.white_player_id.clone(),
+            entry.black_player_id.clone(),
+            entry.result,
+            options,
+            data,
+        )?;
+        new_entry.timestamp = entry.timestamp.clone();
+        for player_id in [&entry.white_player_id, &entry.black_player_id] {
+            if let Some(player) = (*data).get_mut(player_id) {
+                player.last_played = Some(entry.timestamp.clone());
+            }
+        }
+        replayed.push(HistoryRecord::Game(new_entry));
+    }
+
+    Ok((replayed, skipped))
+}
+
+// The highest combined rating `player_id` had after one of their records in `history`, from
+// the ratings stored in it, and the timestamp of that record. Returns None if the player has
+// no records.
+fn peak_rating(
+    player_id: &str,
+    history: &[HistoryRecord],
+    data: &HashMap<String, PlayerRecord>,
+) -> Option<(f64, String)> {
+    let stored = StoredRatings::new(history, data);
+    let mut peak: Option<(f64, String)> = None;
+    for (i, record) in history.iter().enumerate() {
+        if !record_player_ids(record).contains(&player_id) {
+            continue;
+        }
+        let (white_rating, black_rating) = stored.after(player_id, Some(i));
+        let rating = (white_rating + black_rating) / 2.;
+        if peak.as_ref().is_none_or(|(highest, _)| rating > *highest) {
+            peak = Some((rating, record_timestamp(record).to_string()));
+        }
+    }
+
+    peak
+}
+
+fn record_player_ids(record: &HistoryRecord) -> Vec<&str> {
+    match record {
+        HistoryRecord::Game(entry) => vec![&entry.white_player_id, &entry.black_player_id],
+        HistoryRecord::Adjustment(adjustment) => vec![&adjustment.player_id],
+        HistoryRecord::TeamGame(game) => game
+            .white_team
+            .iter()
+            .chain(&game.black_team)
+            .map(String::as_str)
+            .collect(),
+        HistoryRecord::FreeForAll(game) => game.ranking.iter().map(String::as_str).collect(),
+        HistoryRecord::Decay(decay) => decay.player_ids.iter().map(String::as_str).collect(),
+    }
+}
+
+fn record_timestamp(record: &HistoryRecord) -> &str {
+    match record {
+        HistoryRecord::Game(entry) => &entry.timestamp,
+        HistoryRecord::Adjustment(adjustment) => &adjustment.timestamp,
+        HistoryRecord::TeamGame(game) => &game.timestamp,
+        HistoryRecord::FreeForAll(game) => &game.timestamp,
+        HistoryRecord::Decay(decay) => &decay.timestamp,
+    }
+}
+
+// The White and Black ratings of the players in the history after each record, worked out from
+// the ratings the records store from before they were applied rather than by replaying them, so
+// that they reflect the options each game was rated with. A rating is unchanged until the next
+// record that stores it, and after the last one it is the player's rating in the table. Games
+// from older history files, which didn't store ratings, are taken to start from the initial
+// rating.
+struct StoredRatings<'a> {
+    // Every rating stored for each player and color, with the index of its record, in history
+    // order.
+    white: HashMap<&'a str, Vec<(usize, Option<f64>)>>,
+    black: HashMap<&'a str, Vec<(usize, Option<f64>)>>,
+    data: &'a HashMap<String, PlayerRecord>,
+}
+
+impl<'a> StoredRatings<'a> {
+    fn new(
+        history: &'a [HistoryRecord],
+        data: &'a HashMap<String, PlayerRecord>,
+    ) -> StoredRatings<'a> {
+        let mut white: HashMap<&str, Vec<(usize, Option<f64>)>> = HashMap::new();
+        let mut black: HashMap<&str, Vec<(usize, Option<f64>)>> = HashMap::new();
+        for (i, record) in history.iter().enumerate() {
+            let mut store = |player_id: &'a str, white_rating, black_rating| {
+                if let Some(rating) = white_rating {
+                    white.entry(player_id).or_default().push((i, rating));
+                }
+                if let Some(rating) = black_rating {
+                    black.entry(player_id).or_default().push((i, rating));
+                }
+            };
+            match record {
+                HistoryRecord::Game(entry) => {
+                    store(&entry.white_player_id, Some(entry.white_rating), None);
+                    store(&entry.black_player_id, None, Some(entry.black_rating));
+                }
+                HistoryRecord::Adjustment(adjustment) => store(
+                    &adjustment.player_id,
+                    Some(Some(adjustment.white_rating)),
+                    Some(Some(adjustment.black_rating)),
+                ),
+                HistoryRecord::TeamGame(game) => {
+                    for (player_id, rating) in game.white_team.iter().zip(&game.white_ratings) {
+                        store(player_id, Some(Some(*rating)), None);
+                    }
+                    for (player_id, rating) in game.black_team.iter().zip(&game.black_ratings) {
+                        store(player_id, None, Some(Some(*rating)));
+                    }
+                }
+                HistoryRecord::FreeForAll(FreeForAllGame {
+                    ranking: player_ids,
+                    white_ratings,
+                    black_ratings,
+                    ..
+                })
+                | HistoryRecord::Decay(Decay {
+                    player_ids,
+                    white_ratings,
+                    black_ratings,
+                    ..
+                }) => {
+                    for (i, player_id) in player_ids.iter().enumerate() {
+                        store(
+                            player_id,
+                            Some(Some(white_ratings[i])),
+                            Some(Some(black_ratings[i])),
+                        );
+                    }
+                }
+            }
+        }
+        StoredRatings { white, black, data }
+    }
+
+    // A player's (White, Black) ratings after the record at `index`, or before the first record
+    // if None. Players who have left the table keep the last rating stored for them.
+    fn after(&self, player_id: &str, index: Option<usize>) -> (f64, f64) {
+        let current = self.data.get(player_id);
+        let rating = |colors: &HashMap<&str, Vec<(usize, Option<f64>)>>, current: Option<f64>| {
+            let stored = colors.get(player_id).map_or(&[][..], Vec::as_slice);
+            let next = stored
+                .iter()
+                .find(|(i, _)| index.is_none_or(|index| *i > index));
+            match (next, current) {
+                (Some((_, rating)), _) => rating.unwrap_or(INITIAL_RATING),
+                (None, Some(current)) => current,
+                (None, None) => stored
+                    .last()
+                    .and_then(|(_, rating)| *rating)
+                    .unwrap_or(INITIAL_RATING),
+            }
+        };
+        (
+            rating(&self.white, current.map(|player| player.white_rating)),
+            rating(&self.black, current.map(|player| player.black_rating)),
+        )
+    }
+}
+
+// Whether a history record is a game of any kind rather than a manual adjustment or a decay.
+fn record_is_game(record: &HistoryRecord) -> bool {
+    !matches!(
+        record,
+        HistoryRecord::Adjustment(_) | HistoryRecord::Decay(_)
+    )
+}
+
+// The games whose logged expected score fell in [low, high), for `calibration`.
+struct CalibrationBucket {
+    low: f64,
+    high: f64,
+    games: usize,
+    // Mean expected and actual score for White.
+    expected: f64,
+    actual: f64,
+}
+
+const CALIBRATION_BUCKETS: usize = 10;
+
+// Groups the games with a logged expected score into bands of White's expected score, so that
+// the model is well calibrated if White's actual score in each band is close to the expected
+// one. Draws count as half a point. Empty bands are left out, and an expected score of exactly
+// 1 goes in the top band.
+fn calibration(history: &[HistoryEntry]) -> Vec<CalibrationBucket> {
+    let mut sums = [(0, 0., 0.); CALIBRATION_BUCKETS];
+    for entry in history {
+        let expected = match entry.expected_score {
+            Some(expected) if (0. ..=1.).contains(&expected) => expected,
+            _ => continue,
+        };
+        let i = ((expected * CALIBRATION_BUCKETS as f64) as usize).min(CALIBRATION_BUCKETS - 1);
+        sums[i].0 += 1;
+        sums[i].1 += expected;
+        sums[i].2 += entry.result.scores().0;
+    }
+    sums.iter()
+        .enumerate()
+        .filter(|(_, (games, _, _))| *games > 0)
+        .map(|(i, (games, expected, actual))| CalibrationBucket {
+            low: i as f64 / CALIBRATION_BUCKETS as f64,
+            high: (i + 1) as f64 / CALIBRATION_BUCKETS as f64,
+            games: *games,
+            expected: expected / *games as f64,
+            actual: actual / *games as f64,
+        })
+        .collect()
+}
+
+// The mean combined rating of the players active in one month, for `drift`.
+struct MonthlyMean {
+    // YYYY-MM.
+    month: String,
+    players: usize,
+    mean: f64,
+}
+
+// The mean rating of each month's active players, from the ratings stored in the history. A
+// player is active in a month if they played a game or were adjusted in it, and the mean is of
+// their ratings after their last record of the month.
+fn rating_drift(
+    history: &[HistoryRecord],
+    data: &HashMap<String, PlayerRecord>,
+) -> Vec<MonthlyMean> {
+    let stored = StoredRatings::new(history, data);
+    let mut drift = Vec::new();
+    let mut month: Option<&str> = None;
+    // Each active player's last record of the month so far.
+    let mut active: HashMap<&str, usize> = HashMap::new();
+    let monthly_mean = |month: &str, active: &HashMap<&str, usize>| MonthlyMean {
+        month: month.to_string(),
+        players: active.len(),
+        mean: active
+            .iter()
+            .map(|(player_id, i)| {
+                let (white_rating, black_rating) = stored.after(player_id, Some(*i));
+                (white_rating + black_rating) / 2.
+            })
+            .sum::<f64>()
+            / active.len() as f64,
+    };
+    for (i, record) in history.iter().enumerate() {
+        let timestamp = record_timestamp(record);
+        let record_month = timestamp.get(..7).unwrap_or(timestamp);
+        if let Some(current) = month.filter(|current| *current != record_month) {
+            drift.push(monthly_mean(current, &active));
+            active.clear();
+        }
+        month = Some(record_month);
+        // Decayed players are by definition inactive.
+        if !matches!(record, HistoryRecord::Decay(_)) {
+            for player_id in record_player_ids(record) {
+                active.insert(player_id, i);
+            }
+        }
+    }
+    if let Some(current) = month {
+        drift.push(monthly_mean(current, &active));
+    }
+
+    drift
+}
+
+fn undo_adjustment(
+    adjustment: &Adjustment,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    let player = match (*data).get_mut(&adjustment.player_id) {
+        Some(player) => player,
+        None => return Err(not_found("adjusted player not found.".to_string())),
+    };
+    player.white_rating = adjustment.white_rating;
+    player.black_rating = adjustment.black_rating;
+
+    Ok(())
+}
+
+fn undo_game(
+    entry: &HistoryEntry,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    // Restore the stored pre-game ratings rather than reversing the rating change, which would
+    // depend on the ratings after the game and so not recover the originals exactly.
+    let (white_rating, black_rating) = match (entry.white_rating, entry.black_rating) {
+        (Some(w), Some(b)) => (w, b),
+        _ => return Err("last game was recorded without ratings and cannot be undone.".into()),
+    };
+    if !(*data).contains_key(&entry.white_player_id) {
+        return Err(not_found("white player not found.".to_string()));
+    }
+    if !(*data).contains_key(&entry.black_player_id) {
+        return Err(not_found("black player not found.".to_string()));
+    }
+
+    let (white_score, black_score) = entry.result.scores();
+    if let Some(white) = (*data).get_mut(&entry.white_player_id) {
+        white.white_rating = white_rating;
+        white.rd = entry.white_rd.unwrap_or(white.rd);
+        white.remove_game(white_score);
+    }
+    if let Some(black) = (*data).get_mut(&entry.black_player_id) {
+        black.black_rating = black_rating;
+        black.rd = entry.black_rd.unwrap_or(black.rd);
+        black.remove_game(black_score);
+    }
+
+    Ok(())
+}
+
+fn undo_decay(decay: &Decay, data: &mut HashMap<String, PlayerRecord>) {
+    for (i, player_id) in decay.player_ids.iter().enumerate() {
+        if let Some(player) = (*data).get_mut(player_id) {
+            player.white_rating = decay.white_ratings[i];
+            player.black_rating = decay.black_ratings[i];
+        }
+    }
+}
+
+fn undo_team_game(
+    game: &TeamGame,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    for player_id in game.white_team.iter().chain(&game.black_team) {
+        if !(*data).contains_key(player_id) {
+            return Err(not_found(format!("player {} not found.", player_id)));
+        }
+    }
+
+    let (white_score, black_score) = game.result.scores();
+    for (team, ratings, score, is_white) in [
+        (&game.white_team, &game.white_ratings, white_score, true),
+        (&game.black_team, &game.black_ratings, black_score, false),
+    ] {
+        for (player_id, rating) in team.iter().zip(ratings) {
+            let player = (*data).get_mut(player_id).unwrap();
+            if is_white {
+                player.white_rating = *rating;
+            } else {
+                player.black_rating = *rating;
+            }
+            player.remove_game(score);
+        }
+    }
+
+    Ok(())
+}
+
+fn undo_ffa_game(
+    game: &FreeForAllGame,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(), Box<dyn Error>> {
+    for player_id in &game.ranking {
+        if !(*data).contains_key(player_id) {
+            return Err(not_found(format!("player {} not found.", player_id)));
+        }
+    }
+
+    // The scores the game was counted as, as in update_ffa_ratings.
+    let opponents = (game.ranking.len() - 1) as f64;
+    for (i, player_id) in game.ranking.iter().enumerate() {
+        let player = (*data).get_mut(player_id).unwrap();
+        player.white_rating = game.white_ratings[i];
+        player.black_rating = game.black_ratings[i];
+        player.remove_game((game.ranking.len() - 1 - i) as f64 / opponents);
+    }
+
+    Ok(())
+}
+
+// Updates ratings for a game and returns the history entry describing it.
+fn record_game(
+    white_player_id: String,
+    black_player_id: String,
+    result: MatchResult,
+    options: &RatingOptions,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(HistoryEntry, RatingChange), Box<dyn Error>> {
+    // Otherwise the player would be rated twice for the one game.
+    if white_player_id == black_player_id {
+        return Err("a player cannot play against themselves.".into());
+    }
+    let white_rd = (*data).get(&white_player_id).map(|p| p.rd);
+    let black_rd = (*data).get(&black_player_id).map(|p| p.rd);
+    let change = match options.system {
+        RatingSystem::Elo => update_ratings(
+            white_player_id.clone(),
+            black_player_id.clone(),
+            result,
+            options,
+            data,
+        )?,
+        RatingSystem::Glicko => update_ratings_glicko(
+            white_player_id.clone(),
+            black_player_id.clone(),
+            result,
+            data,
+        )?,
+    };
+    let entry = HistoryEntry {
+        white_player_id,
+        black_player_id,
+        result,
+        timestamp: timestamp(),
+        white_rating: Some(change.white_before),
+        black_rating: Some(change.black_before),
+        white_rd,
+        black_rd,
+        expected_score: Some(expected_score_with_scale(
+            change.white_before,
+            change.black_before,
+            options.scale(),
+        )),
+    };
+
+    Ok((entry, change))
+}
+
+// Like record_game, for a game between two teams.
+fn record_team_game(
+    white_team: Vec<String>,
+    black_team: Vec<String>,
+    result: MatchResult,
+    options: &RatingOptions,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(TeamGame, Vec<PlayerChange>), Box<dyn Error>> {
+    let changes = update_team_ratings(&white_team, &black_team, result, options, data)?;
+    let (white_changes, black_changes) = changes.split_at(white_team.len());
+    let game = TeamGame {
+        white_ratings: white_changes.iter().map(|change| change.before).collect(),
+        black_ratings: black_changes.iter().map(|change| change.before).collect(),
+        white_team,
+        black_team,
+        result,
+        timestamp: timestamp(),
+    };
+
+    Ok((game, changes))
+}
+
+// Like record_game, for a free-for-all game.
+fn record_ffa_game(
+    ranking: Vec<String>,
+    options: &RatingOptions,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<(FreeForAllGame, Vec<PlayerChange>), Box<dyn Error>> {
+    let before: Vec<&PlayerRecord> = ranking.iter().filter_map(|id| (*data).get(id)).collect();
+    let white_ratings = before.iter().map(|player| player.white_rating).collect();
+    let black_ratings = before.iter().map(|player| player.black_rating).collect();
+    let changes = update_ffa_ratings(&ranking, options, data)?;
+    let game = FreeForAllGame {
+        ranking,
+        timestamp: timestamp(),
+        white_ratings,
+        black_ratings,
+    };
+
+    Ok((game, changes))
+}
+
+// Reads commands from `input` until quit or the end of input, applying them to the table in
+// memory. Each line is parsed as the arguments to leelo with the filename left off, so the
+// commands take the same flags as on the command line. A bad line is reported and the session
+// carries on.
+fn run_repl<R: io::BufRead>(
+    input: R,
+    filename: &str,
+    store: &dyn Store,
+    autosave: bool,
+    prompt: bool,
+    quiet: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+    store.read(&mut data)?;
+    // Games not yet logged to the history, which is only appended to once the table is saved
+    // so that the two never disagree.
+    let mut unsaved: Option<Vec<HistoryRecord>> = None;
+
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            print!("leelo> ");
+            io::Write::flush(&mut io::stdout())?;
+        }
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let words: Vec<String> = line.split_whitespace().map(String::from).collect();
+        let save = match words.first().map(String::as_str) {
+            None => continue,
+            Some("quit") | Some("exit") => break,
+            Some("save") => true,
+            Some(_) => match repl_command(words, filename, &mut data) {
+                Ok(Some(records)) => {
+                    unsaved.get_or_insert_with(Vec::new).extend(records);
+                    autosave
+                }
+                Ok(None) => false,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    false
+                }
+            },
+        };
+        if save {
+            if let Err(e) = save_repl_table(filename, store, &data, &mut unsaved, quiet) {
+                eprintln!("Error: {}", e);
+            }
+        }
+    }
+
+    if unsaved.is_some() {
+        eprintln!("Warning: quitting with unsaved changes.");
+    }
+    Ok(())
+}
+
+// Runs one repl command against `data`, returning the history records to log if it changed
+// the table.
+fn repl_command(
+    words: Vec<String>,
+    filename: &str,
+    data: &mut HashMap<String, PlayerRecord>,
+) -> Result<Option<Vec<HistoryRecord>>, Box<dyn Error>> {
+    let mut args = vec!["leelo".to_string()];
+    args.extend(words);
+    args.extend(["--file".to_string(), filename.to_string()]);
+    match Config::parse(&args)?.operation {
+        Operation::AddPlayer { player_id, rating } => {
+            create_player(player_id, rating, data)?;
+            Ok(Some(Vec::new()))
+        }
+        Operation::Update {
+            white_player_id,
+            black_player_id,
+            result,
+            options,
+            dry_run,
+        } => {
+            if dry_run {
+                let (_, change) = record_game(
+                    white_player_id,
+                    black_player_id,
+                    result,
+                    &options,
+                    &mut data.clone(),
+                )?;
+                println!("{}", format_rating_change(&change));
+                return Ok(None);
+            }
+            let (entry, change) =
+                record_game(white_player_id, black_player_id, result, &options, data)?;
+            println!("{}", format_rating_change(&change));
+            Ok(Some(vec![HistoryRecord::Game(entry)]))
+        }
+        Operation::View {
+            format,
+            order,
+            mut options,
+        } => {
+            options.color &= io::stdout().is_terminal();
+            render(format, &sort_standings(data, order), &options)?;
+            Ok(None)
+        }
+        _ => Err("only game, player, view, save and quit can be used in the repl.".into()),
+    }
+}
+
+fn save_repl_table(
+    filename: &str,
+    store: &dyn Store,
+    data: &HashMap<String, PlayerRecord>,
+    unsaved: &mut Option<Vec<HistoryRecord>>,
+    quiet: bool,
+) -> Result<(), Box<dyn Error>> {
+    store.write(data)?;
+    // As with a single game, the ratings are already saved, so a failure to log a game
+    // shouldn't be fatal.
+    for record in unsaved.take().unwrap_or_default() {
+        if let Err(e) = append_history(filename, &record) {
+            eprintln!("Warning: unable to record game history: {}", e);
+        }
+    }
+    if !quiet {
+        eprintln!("Saved {}.", filename);
+    }
+    Ok(())
+}
+
+// The probabilities that A wins, ties and loses a match of `games` games, in which A's expected
+// score in each game is `score`. Games are assumed to be independent, with the same expected
+// score whatever the colors, and each is drawn with probability `draw_rate`. The rest of the
+// expected score is made up of wins, so A wins a game with probability score - draw_rate / 2.
+// The draw rate is capped where that would leave either player a negative chance of winning.
+// The match goes to whoever scores more than half the points. Without draws this also gives
+// the odds of a best-of-N match, since playing out the remaining games can't change its winner.
+fn match_odds(score: f64, draw_rate: f64, games: usize) -> (f64, f64, f64) {
+    let draw = draw_rate.min(2. * score.min(1. - score));
+    let win = (score - draw / 2.).max(0.);
+    let loss = (1. - score - draw / 2.).max(0.);
+
+    // The chance of each total after the games so far, in half points.
+    let mut totals = vec![1.];
+    for _ in 0..games {
+        let mut next = vec![0.; totals.len() + 2];
+        for (half_points, p) in totals.iter().enumerate() {
+            next[half_points] += p * loss;
+            next[half_points + 1] += p * draw;
+            next[half_points + 2] += p * win;
+        }
+        totals = next;
+    }
+
+    let (mut a_wins, mut tied, mut b_wins) = (0., 0., 0.);
+    for (half_points, p) in totals.into_iter().enumerate() {
+        match half_points.cmp(&games) {
+            Ordering::Greater => a_wins += p,
+            Ordering::Equal => tied += p,
+            Ordering::Less => b_wins += p,
+        }
+    }
+    (a_wins, tied, b_wins)
+}
+
+// Fair (decimal, moneyline) odds for a win probability, or None when the outcome is certain
+// and the odds are unbounded. Moneyline odds are negative for favourites (the stake needed to
+// win 100) and positive for underdogs (the winnings on a stake of 100).
+fn fair_odds(probability: f64) -> Option<(f64, f64)> {
+    if !(probability > 0. && probability < 1.) {
+        return None;
+    }
+    let moneyline = if probability >= 0.5 {
+        -100. * probability / (1. - probability)
+    } else {
+        100. * (1. - probability) / probability
+    };
+    Some((1. / probability, moneyline))
+}
+
+// What `predict` prints: the rating difference, then each player's expected score and fair
+// decimal and moneyline odds, one line each.
+fn format_prediction(
+    player_a: &str,
+    rating_a: f64,
+    player_b: &str,
+    rating_b: f64,
+    scale: f64,
+) -> String {
+    let score_a = expected_score_with_scale(rating_a, rating_b, scale);
+    let mut out = format!(
+        "Rating difference: {:+}\n",
+        (rating_a - rating_b).round() as i64
+    );
+    for (player_id, score) in [(player_a, score_a), (player_b, 1. - score_a)] {
+        match fair_odds(score) {
+            Some((decimal, moneyline)) => out.push_str(&format!(
+                "{}\t{:.1}%\t{:.2}\t{:+.0}\n",
+                player_id,
+                100. * score,
+                decimal,
+                moneyline
+            )),
+            None => out.push_str(&format!("{}\t{:.1}%\t-\t-\n", player_id, 100. * score)),
+        }
+    }
+    out
+}
+
+// The rating change `forecast` prints for a hypothetical game, e.g. "alice\t1000 -> 1030 (+30)".
+// The result is from the player's point of view, as if they had White.
+fn format_forecast(
+    player_id: &str,
+    rating: f64,
+    games: u32,
+    opponent_rating: f64,
+    result: MatchResult,
+    scale: f64,
+) -> String {
+    let (new_rating, _) =
+        new_ratings_with_scale(rating, opponent_rating, result, k_factor(games), K, scale);
+    format!(
+        "{}\t{} -> {} ({:+})",
+        player_id,
+        rating.round() as i64,
+        new_rating.round() as i64,
+        (new_rating - rating).round() as i64
+    )
+}
+
+// Summarises a game's rating changes on one line, e.g. "White: 1000 -> 1020 (+20), Black: ...".
+fn format_rating_change(change: &RatingChange) -> String {
+    let side = |before: f64, after: f64| {
+        format!(
+            "{} -> {} ({:+})",
+            before.round() as i64,
+            after.round() as i64,
+            (after - before).round() as i64
+        )
+    };
+    format!(
+        "White: {}, Black: {}",
+        side(change.white_before, change.white_after),
+        side(change.black_before, change.black_after)
+    )
+}
+
+// Like format_rating_change, for the players of a team or free-for-all game, e.g.
+// "alice: 1000 -> 1010 (+10), bob: ...".
+fn format_player_changes(changes: &[PlayerChange]) -> String {
+    changes
+        .iter()
+        .map(|change| {
+            format!(
+                "{}: {} -> {} ({:+})",
+                change.player_id,
+                change.before.round() as i64,
+                change.after.round() as i64,
+                (change.after - change.before).round() as i64
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+pub fn run(config: Config) -> Result<(), LeeloError> {
+    execute(config).map_err(LeeloError::from)
+}
+
+fn execute(config: Config) -> Result<(), Box<dyn Error>> {
+    match config.operation {
+        Operation::Version => {
+            println!("leelo {}", env!("CARGO_PKG_VERSION"));
+        }
+        Operation::Help => {
+            println!("A simple Elo rating implementation.");
+            println!();
+            println!("USAGE:");
+            println!(
+                "\tleelo [COMMAND] [ARGUMENTS] [--backend <csv|sqlite>] [--quiet] [--no-backup] [--no-header]"
+            );
+            println!();
+            println!("Tables ending in .db are stored in SQLite, anything else as CSV.");
+            println!("Before a table is overwritten it is copied to <file>.bak, unless --no-backup is given.");
+            println!("Scores are 1-0, 0-1 or 0.5-0.5, or equivalently white, black or draw.");
+            println!(
+                "--scale sets the rating scale (default {}); 173.7178 gives FIDE-style Elo.",
+                RATING_CONST
+            );
+            println!("CSV tables are expected to start with a header row; use --no-header to read and write one without.");
+            println!("CSV tables are comma-separated; use --delimiter <char>, e.g. --delimiter ';', for another separator.");
+            println!("<file> can also be given anywhere as --file <path>.");
+            println!("If <file> is omitted it is read from LEELO_FILE; an explicit <file> wins.");
+            println!();
+            println!("EXIT CODES:");
+            println!(
+                "\t1 other error, 2 invalid arguments, 3 file error, 4 player or data not found"
+            );
+            println!();
+            println!("COMMANDS:");
+            println!("\thelp");
+            println!("\t\t\tPrint help information");
+            println!("\t--version");
+            println!("\t\t\tPrint the version");
+            println!("\ttarget <id> <rank> <file>");
+            println!(
+                "\t\t\tShow the rating a player needs to reach <rank> in the current standings"
+            );
+            println!("\tcalibration <file>");
+            println!("\t\t\tCompare the expected scores logged with each game to the actual results, in bands of 10%");
+            println!("\tdrift <file>");
+            println!("\t\t\tShow how the mean rating of active players changed month by month, from the ratings in the history");
+            println!("\tpeak <id> <file>");
+            println!("\t\t\tFind a player's highest rating and when it was reached, from the ratings in the history");
+            println!("\th2h <a> <b> <file>");
+            println!("\t\t\tShow the record between two players");
+            println!("\tundo <file>");
+            println!("\t\t\tRoll back the most recent game, adjustment or decay");
+            println!("\tdecay <file>");
+            println!("\t\t\tDecay the ratings of inactive players towards the initial rating");
+            println!("\tmerge <a> <b> <out> [--on-conflict <keep-a|keep-b|average>]");
+            println!("\t\t\tCombine two tables into a new one");
+            println!("\tnew <file> [--force]");
+            println!(
+                "\t\t\tCreate new leelo table. An existing file is only replaced with --force"
+            );
+            println!("\tgame <white> <black> <score> <file> [--k <value>] [--margin <m>] [--weight <factor>] [--max-change <points>] [--upset-bonus] [--floor <rating>] [--ceiling <rating>] [--scale <s>] [--no-draw-change <points>] [--system <elo|glicko>] [--dry-run]");
+            println!("\t\t\tRecord results of a game and update ratings");
+            println!(
+                "\t\t\t(<score> is 1-0, 0-1, 0.5-0.5 or any split of the point such as 0.75-0.25)"
+            );
+            println!("\t\t\t(--no-draw-change leaves ratings unchanged after a draw between players at most <points> apart)");
+            println!("\tlist <file> [--format <text|csv|json|markdown|html>]");
+            println!("\t\t\tList player ids alphabetically");
+            println!("\ttop <n> <file> [--format <text|csv|json|markdown|html>] [--precision <n>]");
+            println!("\t\t\tView the n highest rated players");
+            println!("\tmostactive <file> [--top <n>]");
+            println!("\t\t\tList the players with the most games, 10 unless --top is given");
+            println!("\trepl <file> [--no-autosave]");
+            println!(
+                "\t\t\tLoad the table once and enter game, player and view commands at a prompt,"
+            );
+            println!("\t\t\twithout the filename. The table is saved after each change, or with --no-autosave");
+            println!("\t\t\tonly on save. Enter quit to leave");
+            println!("\tprune --below <rating> <file> [--dry-run]");
+            println!("\t\t\tRemove every player rated below <rating>");
+            println!("\tinactive <file> [--below <n>] [--format <text|csv|json|markdown|html>]");
+            println!("\t\t\tList players with no games, or with fewer than n games");
+            println!("\tshow <id> <file> [--precision <n>]");
+            println!("\t\t\tShow a single player's ratings and record");
+            println!("\tverify <file>");
+            println!("\t\t\tCheck every row of the table and report all problems found");
+            println!("\tstats <file> [--format <text|csv|json|markdown|html>]");
+            println!("\t\t\tSummarise the ratings in the table");
+            println!("\troundrobin <file>");
+            println!("\t\t\tPrint a schedule in which every player meets every other once");
+            println!("\texpected <file> [--players <ids>] [--scale <s>]");
+            println!("\t\t\tPredict each player's points if everyone (or the comma-separated ids) plays everyone once");
+            println!("\tswiss <file>");
+            println!("\t\t\tPair the next Swiss round from the scores in the game history");
+            println!("\tpredict <a> <b> <file> [--scale <s>]");
+            println!("\t\t\tShow the expected score of a game between two players, with fair decimal and moneyline odds");
+            println!("\tmatch-odds <a> <b> <games> <file> [--draw-rate <p>] [--scale <s>]");
+            println!("\t\t\tShow each player's chance of winning a match of <games> games, and of a tied match,");
+            println!("\t\t\tassuming independent games drawn with probability <p> (0 by default)");
+            println!("\tforecast <player> <opponent> <score> <file> [--scale <s>]");
+            println!("\t\t\tShow a player's rating after a hypothetical game, without saving");
+            println!("\twhatif <white> <black> <score> <file> --k1 <k> --k2 <k>");
+            println!("\t\t\tCompare the ratings after a hypothetical game under two K-factors, without saving");
+            println!("\trebuild <file> [--k <value>] [--system <elo|glicko>]");
+            println!(
+                "\t\t\tRecompute every rating from each player's starting rating by replaying the history"
+            );
+            println!("\tsimulate <players> <games> [--seed <n>]");
+            println!("\t\t\tSimulate games between players of known strength to see how well ratings converge");
+            println!("\thistory <file> [--since <date>] [--limit <n>] [--offset <n>]");
+            println!("\t\t\tView the log of recorded games, optionally only those on or after an ISO-8601 date");
+            println!("\t\t\tor only the <n> most recent, skipping the latest --offset of them to page back");
+            println!("\tgame <file> --batch <game> [<game> ...]");
+            println!("\t\t\tRecord several games, each given as \"<white> <black> <score>\", writing the table once");
+            println!("\t\t\t(everything after --batch is a game, so other flags go before it)");
+            println!("\tgame --team <white_ids> <black_ids> <score> <file>");
+            println!("\t\t\tRecord a game between teams given as comma-separated ids");
+            println!("\tffa <first> <second> [<third> ...] <file> [--k <value>] [--dry-run]");
+            println!("\t\t\tRecord a free-for-all game from its finishing order, winner first");
+            println!("\timport <games> <file> [--dedup]");
+            println!("\t\t\tRecord every game in a CSV of white,black,score rows");
+            println!("\t\t\t(use - as <games> to read the rows from stdin)");
+            println!(
+                "\t\t\tWith --dedup, rows repeating an earlier white,black,score and optional"
+            );
+            println!("\t\t\tfourth timestamp column are skipped");
+            println!("\tplayer <id> <file> [--initial <rating> | --rating <rating>]");
+            println!(
+                "\t\t\tCreate new player, optionally seeded at <rating> rather than the default"
+            );
+            println!("\tplayer --from <ids_file> <file> [--initial <rating>]");
+            println!("\t\t\tCreate every player listed one per line in ids_file");
+            println!("\tremove <id> <file>");
+            println!("\t\t\tRemove player");
+            println!("\treset <id> <file> [--clear-games]");
+            println!("\t\t\tReset a player's rating to the initial rating");
+            println!("\tadjust <id> <delta> <file>");
+            println!("\t\t\tAdd a signed delta to a player's rating, e.g. for a forfeit");
+            println!("\trename <old> <new> <file>");
+            println!("\t\t\tChange a player's id, keeping their rating");
+            println!("\tview <file> [--format <text|csv|json|markdown|html>] [--records] [--combined] [--rank] [--precision <n>] [--sort <rating-desc|rating-asc|name|games>] [--color] [--classes]");
+            println!("\t\t\tView players and their White and Black ratings");
+            println!("\t\t\t(--classes labels each player with their USCF class, from J up to Senior Master)");
+            println!(
+                "\t\t\t(--color shows the top three in gold, silver and bronze on a terminal)"
+            );
+            println!(
+                "\t\t\t(? marks players with fewer than {} games)",
+                UNCERTAIN_GAMES
+            );
+        }
+        Operation::New { force } => {
+            let data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            if !force && Path::new(&filename).exists() {
+                return Err(format!(
+                    "{} already exists. Use --force to replace it with an empty table.",
+                    filename
+                )
+                .into());
+            }
+            let store = open_store(&filename, &config.store);
+            store.write(&data)?;
+        }
+        Operation::Update {
+            white_player_id,
+            black_player_id,
+            result,
+            options,
+            dry_run,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let (entry, change) = record_game(
+                white_player_id,
+                black_player_id,
+                result,
+                &options,
+                &mut data,
+            )?;
+            if dry_run {
+                eprintln!("Dry run, ratings not saved.");
+                println!("{}", format_rating_change(&change));
+                return Ok(());
+            }
+            store.write(&data)?;
+            if !config.quiet {
+                eprintln!("{}", format_rating_change(&change));
+            }
+
+            // The ratings are already saved, so a failure to log the game shouldn't be fatal.
+            if let Err(e) = append_history(&filename, &HistoryRecord::Game(entry)) {
+                eprintln!("Warning: unable to record game history: {}", e);
+            }
+        }
+        Operation::BatchGames {
+            games,
+            options,
+            dry_run,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            // Games are applied in order. A game that fails is reported and skipped, and the
+            // table is written once at the end.
+            let mut entries = Vec::new();
+            for (i, game) in games.iter().enumerate() {
+                let recorded = parse_batch_game(game).and_then(|(white, black, result)| {
+                    record_game(white, black, result, &options, &mut data)
+                });
+                match recorded {
+                    Ok((entry, change)) => {
+                        if dry_run {
+                            println!("{}: {}", game, format_rating_change(&change));
+                        } else if !config.quiet {
+                            eprintln!("{}: {}", game, format_rating_change(&change));
+                        }
+                        entries.push(HistoryRecord::Game(entry));
+                    }
+                    Err(e) => eprintln!("Warning: game {} ({}) failed: {}", i + 1, game, e),
+                }
+            }
+            let failed = games.len() - entries.len();
+
+            if dry_run {
+                eprintln!("Dry run, ratings not saved.");
+            } else {
+                store.write(&data)?;
+                for entry in &entries {
+                    if let Err(e) = append_history(&filename, entry) {
+                        eprintln!("Warning: unable to record game history: {}", e);
+                        break;
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(format!("{} of {} games failed.", failed, games.len()).into());
+            }
+        }
+        Operation::TeamGame {
+            white_team,
+            black_team,
+            result,
+            options,
+            dry_run,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let (game, changes) =
+                record_team_game(white_team, black_team, result, &options, &mut data)?;
+            let summary = format_player_changes(&changes);
+            if dry_run {
+                eprintln!("Dry run, ratings not saved.");
+                println!("{}", summary);
+                return Ok(());
+            }
+            store.write(&data)?;
+            if !config.quiet {
+                eprintln!("{}", summary);
+            }
+
+            if let Err(e) = append_history(&filename, &HistoryRecord::TeamGame(game)) {
+                eprintln!("Warning: unable to record game history: {}", e);
+            }
+        }
+        Operation::FreeForAll {
+            ranking,
+            options,
+            dry_run,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let (game, changes) = record_ffa_game(ranking, &options, &mut data)?;
+            let summary = format_player_changes(&changes);
+            if dry_run {
+                eprintln!("Dry run, ratings not saved.");
+                println!("{}", summary);
+                return Ok(());
+            }
+            store.write(&data)?;
+            if !config.quiet {
+                eprintln!("{}", summary);
+            }
+
+            if let Err(e) = append_history(&filename, &HistoryRecord::FreeForAll(game)) {
+                eprintln!("Warning: unable to record game history: {}", e);
+            }
+        }
+        Operation::Import {
+            games_filename,
+            dedup,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let mut games = read_games(&games_filename)?;
+            if dedup {
+                let (unique, duplicates) = dedup_games(games);
+                games = unique;
+                if !config.quiet {
+                    eprintln!("Skipped {} duplicate games.", duplicates);
+                }
+            }
+            // Games are applied in order, and nothing is written unless every game succeeds.
+            let mut entries = Vec::new();
+            for game in games {
+                let (entry, _) = record_game(
+                    game.white_player_id,
+                    game.black_player_id,
+                    game.result,
+                    &RatingOptions::default(),
+                    &mut data,
+                )
+                .map_err(|e| LeeloError::from(e).context(&format!("line {}: ", game.line)))?;
+                entries.push(HistoryRecord::Game(entry));
+            }
+            store.write(&data)?;
+
+            for entry in &entries {
+                if let Err(e) = append_history(&filename, entry) {
+                    eprintln!("Warning: unable to record game history: {}", e);
+                    break;
+                }
+            }
+            if !config.quiet {
+                eprintln!("Imported {} games.", entries.len());
+            }
+        }
+        Operation::AddPlayer { player_id, rating } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            create_player(player_id, rating, &mut data)?;
+            store.write(&data)?;
+        }
+        Operation::AddPlayers {
+            ids_filename,
+            rating,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let player_ids = read_player_ids(&ids_filename)?;
+            let added = player_ids.len();
+            let skipped = create_players(player_ids, rating, &mut data)?;
+            store.write(&data)?;
+            for player_id in &skipped {
+                eprintln!("Warning: player {} already exists, skipping.", player_id);
+            }
+            if !config.quiet {
+                eprintln!("Added {} players.", added - skipped.len());
+            }
+        }
+        Operation::RemovePlayer(player_id) => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            remove_player(player_id, &mut data)?;
+            store.write(&data)?;
+        }
+        Operation::Reset {
+            player_id,
+            clear_games,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            reset_player(player_id, clear_games, &mut data)?;
+            store.write(&data)?;
+        }
+        Operation::Rename { old_id, new_id } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            rename_player(old_id.clone(), new_id.clone(), &mut data)?;
+            store.write(&data)?;
+
+            if Path::new(&history_filename(&filename)).exists() {
+                let mut history = read_history_records(&filename)?;
+                rename_in_history(&mut history, &old_id, &new_id);
+                write_history(&filename, &history)?;
+            }
+        }
+        Operation::Adjust { player_id, delta } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let adjustment = adjust_player(player_id, delta, &mut data)?;
+            store.write(&data)?;
+
+            // Only tables that already keep a history get one; a failure to log is not fatal.
+            if Path::new(&history_filename(&filename)).exists() {
+                if let Err(e) = append_history(&filename, &HistoryRecord::Adjustment(adjustment)) {
+                    eprintln!("Warning: unable to record adjustment history: {}", e);
+                }
+            }
+        }
+        Operation::View {
+            format,
+            order,
+            mut options,
+        } => {
+            // Escape codes would only clutter a file or another program's input.
+            options.color &= io::stdout().is_terminal();
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            render(format, &sort_standings(&data, order), &options)?;
+        }
+        Operation::Top {
+            n,
+            format,
+            precision,
+        } => {
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+
+            let top = store.top(n)?;
+            let data_vec: Vec<(&String, &PlayerRecord)> = top
+                .iter()
+                .map(|(player_id, player)| (player_id, player))
+                .collect();
+            let options = ViewOptions {
+                precision,
+                ..ViewOptions::default()
+            };
+            render(format, &data_vec, &options)?;
+        }
+        Operation::List(format) => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let player_ids: Vec<&String> = sort_standings(&data, SortOrder::Name)
+                .into_iter()
+                .map(|(player_id, _)| player_id)
+                .collect();
+            render_player_ids(format, &player_ids, io::stdout())?;
+        }
+        Operation::MostActive(n) => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            for (player_id, player) in sort_standings(&data, SortOrder::Games).iter().take(n) {
+                println!("{}\t{}", player_id, player.games);
+            }
+        }
+        Operation::Inactive { below, format } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let player_ids: Vec<&String> = sort_standings(&data, SortOrder::Name)
+                .into_iter()
+                .filter(|(_, player)| player.games < below)
+                .map(|(player_id, _)| player_id)
+                .collect();
+            render_player_ids(format, &player_ids, io::stdout())?;
+        }
+        Operation::Show {
+            player_id,
+            precision,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let player = match data.get(&player_id) {
+                Some(player) => player,
+                None => return Err(player_not_found("player", &player_id, &data)),
+            };
+            write_player_details(&player_id, player, precision, io::stdout())?;
+        }
+        Operation::Repl { autosave } => {
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            let prompt = io::stdin().is_terminal();
+            run_repl(
+                io::stdin().lock(),
+                &filename,
+                store.as_ref(),
+                autosave,
+                prompt,
+                config.quiet,
+            )?;
+        }
+        Operation::Prune { below, dry_run } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let pruned = prune_players(below, &mut data);
+            if dry_run {
+                eprintln!("Dry run, {} players would be removed.", pruned.len());
+                for player_id in &pruned {
+                    println!("{}", player_id);
+                }
+                return Ok(());
+            }
+            store.write(&data)?;
+            if !config.quiet {
+                eprintln!("Removed {} players.", pruned.len());
+            }
+        }
+        Operation::Verify => {
+            let filename = config.filename.unwrap();
+            let problems = open_store(&filename, &config.store).verify()?;
+            for problem in &problems {
+                println!("{}", problem);
+            }
+            if !problems.is_empty() {
+                return Err(format!("found {} problems in {}.", problems.len(), filename).into());
+            }
+            if !config.quiet {
+                eprintln!("No problems found in {}.", filename);
+            }
+        }
+        Operation::Stats(format) => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            match table_stats(&data) {
+                Some(stats) => render_stats(format, &stats, io::stdout())?,
+                None => println!("No players in table."),
+            }
+        }
+        Operation::RoundRobin => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let player_ids: Vec<&str> = sort_standings(&data, SortOrder::Name)
+                .into_iter()
+                .map(|(player_id, _)| player_id.as_str())
+                .collect();
+            for (i, round) in round_robin(&player_ids).iter().enumerate() {
+                println!("Round {}", i + 1);
+                for (white, black) in round {
+                    match black {
+                        Some(black) => println!("\t{} - {}", white, black),
+                        None => println!("\t{} - bye", white),
+                    }
+                }
+            }
+        }
+        Operation::ExpectedStandings { player_ids, scale } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let player_ids = match player_ids {
+                Some(player_ids) => player_ids,
+                None => data.keys().cloned().collect(),
+            };
+            let mut players = Vec::new();
+            for player_id in &player_ids {
+                match data.get(player_id) {
+                    Some(player) => players.push((player_id.as_str(), player.rating())),
+                    None => return Err(not_found(format!("player {} not found.", player_id))),
+                }
+            }
+            for (player_id, points) in expected_round_robin(&players, scale.unwrap_or(RATING_CONST))
+            {
+                println!("{}\t{:.2}", player_id, points);
+            }
+        }
+        Operation::Swiss => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            // The first round is paired on rating alone.
+            let history = if Path::new(&history_filename(&filename)).exists() {
+                read_history(&filename)?
+            } else {
+                Vec::new()
+            };
+
+            let standings: Vec<&str> = sort_standings(&data, SortOrder::RatingDesc)
+                .into_iter()
+                .map(|(player_id, _)| player_id.as_str())
+                .collect();
+            for (white, black) in swiss_pairings(&standings, &history) {
+                match black {
+                    Some(black) => println!("\t{} - {}", white, black),
+                    None => println!("\t{} - bye", white),
+                }
+            }
+        }
+        Operation::Predict {
+            player_a,
+            player_b,
+            scale,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let rating_a = get_rating(&player_a, &data)?;
+            let rating_b = get_rating(&player_b, &data)?;
+
+            print!(
+                "{}",
+                format_prediction(&player_a, rating_a, &player_b, rating_b, scale)
+            );
+        }
+        Operation::MatchOdds {
+            player_a,
+            player_b,
+            games,
+            draw_rate,
+            scale,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let rating_a = get_rating(&player_a, &data)?;
+            let rating_b = get_rating(&player_b, &data)?;
+
+            let score_a = expected_score_with_scale(rating_a, rating_b, scale);
+            let (a_wins, tied, b_wins) = match_odds(score_a, draw_rate, games);
+            println!("{}\t{:.1}%", player_a, 100. * a_wins);
+            println!("{}\t{:.1}%", player_b, 100. * b_wins);
+            println!("Tied\t{:.1}%", 100. * tied);
+        }
+        Operation::Forecast {
+            player_id,
+            opponent_id,
+            result,
+            scale,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let rating = get_rating(&player_id, &data)?;
+            let opponent_rating = get_rating(&opponent_id, &data)?;
+
+            println!(
+                "{}",
+                format_forecast(
+                    &player_id,
+                    rating,
+                    data[&player_id].games,
+                    opponent_rating,
+                    result,
+                    scale
+                )
+            );
+        }
+        Operation::WhatIf {
+            white_player_id,
+            black_player_id,
+            result,
+            k_values,
+        } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            if white_player_id == black_player_id {
+                return Err("a player cannot play against themselves.".into());
+            }
+            let white_rating = match data.get(&white_player_id) {
+                Some(player) => player.white_rating,
+                None => return Err(player_not_found("white player", &white_player_id, &data)),
+            };
+            let black_rating = match data.get(&black_player_id) {
+                Some(player) => player.black_rating,
+                None => return Err(player_not_found("black player", &black_player_id, &data)),
+            };
+
+            // The same K is used for both players, so the changes mirror each other.
+            for k in k_values {
+                let (white_after, black_after) =
+                    new_ratings_with_k(white_rating, black_rating, result, k, k);
+                let change = RatingChange {
+                    white_before: white_rating,
+                    white_after,
+                    black_before: black_rating,
+                    black_after,
+                };
+                println!("K {}\t{}", k, format_rating_change(&change));
+            }
+        }
+        Operation::Rebuild(options) => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let (history, skipped) =
+                rebuild_ratings(&read_history_records(&filename)?, &options, &mut data)?;
+            store.write(&data)?;
+            // The history is rewritten with the replayed pre-game ratings so that undo still works.
+            write_history(&filename, &history)?;
+            if skipped > 0 {
+                eprintln!(
+                    "Warning: skipped {} games involving players no longer in the table.",
+                    skipped
+                );
+            }
+            if !config.quiet {
+                eprintln!("Replayed {} games.", history.len() - skipped);
+            }
+        }
+        Operation::Simulate {
+            players,
+            games,
+            seed,
+        } => {
+            if !config.quiet {
+                eprintln!("Simulating with seed {}.", seed);
+            }
+            println!("Games\tRMS error\tCorrelation");
+            for checkpoint in simulate(players, games, seed) {
+                println!(
+                    "{}\t{:.1}\t\t{:.3}",
+                    checkpoint.games, checkpoint.rms_error, checkpoint.correlation
+                );
+            }
+        }
+        Operation::History {
+            since,
+            limit,
+            offset,
+        } => {
+            let filename = config.filename.unwrap();
+            let mut records = read_history_records(&filename)?;
+            if let Some(since) = since {
+                records.retain(|record| recorded_since(record, since));
+            }
+            for record in history_page(records, limit, offset) {
+                match record {
+                    HistoryRecord::Game(entry) => println!(
+                        "{}\t{} {} {}",
+                        entry.timestamp, entry.white_player_id, entry.result, entry.black_player_id
+                    ),
+                    HistoryRecord::Adjustment(adjustment) => println!(
+                        "{}\t{} adjusted by {:+}",
+                        adjustment.timestamp, adjustment.player_id, adjustment.delta
+                    ),
+                    HistoryRecord::TeamGame(game) => println!(
+                        "{}\t{} {} {}",
+                        game.timestamp,
+                        game.white_team.join(","),
+                        game.result,
+                        game.black_team.join(",")
+                    ),
+                    HistoryRecord::FreeForAll(game) => {
+                        println!("{}\tffa {}", game.timestamp, game.ranking.join(","))
+                    }
+                    HistoryRecord::Decay(decay) => println!(
+                        "{}\tdecayed {}",
+                        decay.timestamp,
+                        decay.player_ids.join(",")
+                    ),
+                }
+            }
+        }
+        Operation::Target { player_id, rank } => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+
+            let rating = match data.get(&player_id) {
+                Some(player) => player.rating(),
+                None => return Err(player_not_found("player", &player_id, &data)),
+            };
+            let data_vec = sort_standings(&data, SortOrder::RatingDesc);
+            let current = data_vec
+                .iter()
+                .zip(standings_ranks(&data_vec))
+                .find(|((id, _), _)| **id == player_id)
+                .map_or(0, |(_, current)| current);
+            match rating_for_rank(&player_id, rank, &data) {
+                Some(needed) if current > rank => println!(
+                    "{} needs {} to reach rank {}, {} more than now.",
+                    player_id,
+                    format_rating(needed, 0),
+                    rank,
+                    format_rating(needed - rating, 0)
+                ),
+                _ => println!(
+                    "{} is already ranked {}, at or above rank {}.",
+                    player_id, current, rank
+                ),
+            }
+        }
+        Operation::Calibration => {
+            let filename = config.filename.unwrap();
+            let history = read_history(&filename)?;
+            let buckets = calibration(&history);
+            for bucket in &buckets {
+                println!(
+                    "{:.0}-{:.0}%\t{} games\texpected {:.1}%\tactual {:.1}%",
+                    100. * bucket.low,
+                    100. * bucket.high,
+                    bucket.games,
+                    100. * bucket.expected,
+                    100. * bucket.actual
+                );
+            }
+            let unlogged = history
+                .iter()
+                .filter(|entry| entry.expected_score.is_none())
+                .count();
+            if buckets.is_empty() {
+                println!("No games with a logged expected score.");
+            }
+            if unlogged > 0 && !config.quiet {
+                eprintln!(
+                    "Left out {} games recorded before expected scores were logged.",
+                    unlogged
+                );
+            }
+        }
+        Operation::Drift => {
+            let filename = config.filename.unwrap();
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            open_store(&filename, &config.store).read(&mut data)?;
+            let drift = rating_drift(&read_history_records(&filename)?, &data);
+            let first = match drift.first() {
+                Some(first) => first,
+                None => {
+                    println!("No games recorded.");
+                    return Ok(());
+                }
+            };
+            for month in &drift {
+                println!(
+                    "{}\t{} active\tmean {}\t{:+}",
+                    month.month,
+                    month.players,
+                    format_rating(month.mean, 0),
+                    (month.mean - first.mean).round() as i64
+                );
+            }
+            let last = drift.last().unwrap();
+            println!(
+                "The mean rating of active players drifted by {:+} from {} to {}.",
+                (last.mean - first.mean).round() as i64,
+                first.month,
+                last.month
+            );
+        }
+        Operation::Peak(player_id) => {
+            let filename = config.filename.unwrap();
+            if !Path::new(&history_filename(&filename)).exists() {
+                return Err(not_found(
+                    "no history recorded for this table, so peak tracking is unavailable."
+                        .to_string(),
+                ));
+            }
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            open_store(&filename, &config.store).read(&mut data)?;
+            match peak_rating(&player_id, &read_history_records(&filename)?, &data) {
+                Some((rating, timestamp)) => {
+                    // The date is enough to say roughly when.
+                    let date = timestamp.split('T').next().unwrap_or_default();
+                    println!(
+                        "{} peaked at {} on {}.",
+                        player_id,
+                        rating.round() as i64,
+                        date
+                    );
+                }
+                None => println!("No games recorded for {}.", player_id),
+            }
+        }
+        Operation::HeadToHead { player_a, player_b } => {
+            let filename = config.filename.unwrap();
+            let (a_wins, b_wins, draws) =
+                head_to_head(&player_a, &player_b, &read_history(&filename)?);
+            if a_wins + b_wins + draws == 0 {
+                println!("No games recorded between {} and {}.", player_a, player_b);
+            } else {
+                println!(
+                    "{} {} - {} {}, {} draws",
+                    player_a, a_wins, b_wins, player_b, draws
+                );
+            }
+        }
+        Operation::Decay => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let decay = decay_ratings(&read_history_records(&filename)?, now(), &mut data);
+            store.write(&data)?;
+            if !config.quiet {
+                eprintln!(
+                    "Decayed the ratings of {} inactive players.",
+                    decay.player_ids.len()
+                );
+            }
+
+            // Logged so that undo and rebuild see the decay.
+            if !decay.player_ids.is_empty() {
+                if let Err(e) = append_history(&filename, &HistoryRecord::Decay(decay)) {
+                    eprintln!("Warning: unable to record game history: {}", e);
+                }
+            }
+        }
+        Operation::Merge {
+            filename_a,
+            filename_b,
+            on_conflict,
+        } => {
+            let filename = config.filename.unwrap();
+            // The output may be one of the inputs, so it's locked before they're read.
+            let _lock = TableLock::acquire(&filename)?;
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let mut data_b: HashMap<String, PlayerRecord> = HashMap::new();
+            open_store(&filename_a, &config.store).read(&mut data)?;
+            open_store(&filename_b, &config.store).read(&mut data_b)?;
+
+            let overlapping = merge_tables(&mut data, data_b, on_conflict);
+            open_store(&filename, &config.store).write(&data)?;
+            if !config.quiet {
+                eprintln!(
+                    "Merged {} players, {} of which were in both tables.",
+                    data.len(),
+                    overlapping
+                );
+            }
+        }
+        Operation::Undo => {
+            let mut data: HashMap<String, PlayerRecord> = HashMap::new();
+            let filename = config.filename.unwrap();
+            let _lock = TableLock::acquire(&filename)?;
+            let store = open_store(&filename, &config.store);
+            store.read(&mut data)?;
+            let mut history = read_history_records(&filename)?;
+            let last = match history.pop() {
+                Some(last) => last,
+                None => return Err(not_found("no games to undo.".to_string())),
+            };
+            match &last {
+                HistoryRecord::Game(entry) => undo_game(entry, &mut data)?,
+                HistoryRecord::Adjustment(adjustment) => undo_adjustment(adjustment, &mut data)?,
+                HistoryRecord::TeamGame(game) => undo_team_game(game, &mut data)?,
+                HistoryRecord::FreeForAll(game) => undo_ffa_game(game, &mut data)?,
+                HistoryRecord::Decay(decay) => undo_decay(decay, &mut data),
+            }
+            if record_is_game(&last) {
+                // Falls back to the players' previous game in the history.
+                for player_id in record_player_ids(&last) {
+                    let previous = history
+                        .iter()
+                        .rev()
+                        .find(|record| {
+                            record_is_game(record) && record_player_ids(record).contains(&player_id)
+                        })
+                        .map(|record| record_timestamp(record).to_string());
+                    if let Some(player) = data.get_mut(player_id) {
+                        player.last_played = previous;
+                    }
+                }
+            }
+            store.write(&data)?;
+            write_history(&filename, &history)?;
+        }
+    };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn temp_table(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("leelo-{}-{}.csv", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn remove_table(path: &Path) {
+        let _ = fs::remove_file(history_filename(path.to_str().unwrap()));
+        let _ = fs::remove_file(backup_filename(path.to_str().unwrap()));
+        fs::remove_file(path).unwrap();
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn config(values: &[&str]) -> Config {
+        Config::new(&args(values)).unwrap()
+    }
+
+    #[test]
+    fn match_result_aliases() {
+        for (score, result) in [
+            ("1-0", MatchResult::WhiteWin),
+            ("White", MatchResult::WhiteWin),
+            ("0-1", MatchResult::BlackWin),
+            ("black", MatchResult::BlackWin),
+            ("0.5-0.5", MatchResult::Draw),
+            ("½-½", MatchResult::Draw),
+            ("draw", MatchResult::Draw),
+            ("d", MatchResult::Draw),
+        ] {
+            assert_eq!(MatchResult::parse(score).unwrap(), result);
+        }
+        for score in ["w", "0.5", ""] {
+            assert_eq!(
+                MatchResult::parse(score).unwrap_err().to_string(),
+                "unable to interpret score argument."
+            );
+        }
+    }
+
+    #[test]
+    fn match_result_parse_partial_scores() {
+        assert_eq!(
+            MatchResult::parse("0.75-0.25").unwrap(),
+            MatchResult::Partial(0.75, 0.25)
+        );
+        assert_eq!(MatchResult::parse("0.7-0.3").unwrap().scores(), (0.7, 0.3));
+        assert_eq!(
+            MatchResult::parse("1.0-0.0").unwrap(),
+            MatchResult::WhiteWin
+        );
+        assert_eq!(MatchResult::Partial(0.25, 0.75).to_string(), "0.25-0.75");
+        assert_eq!(
+            MatchResult::parse("1-1").unwrap_err().to_string(),
+            "scores must sum to 1, got 1-1."
+        );
+        assert_eq!(
+            MatchResult::parse("2-0").unwrap_err().to_string(),
+            "scores must be between 0 and 1, got 2-0."
+        );
+
+        // The rating change scales with the score, a quarter of the way from a draw to a win.
+        let (white, _) = new_ratings(1000., 1000., MatchResult::Partial(0.75, 0.25));
+        assert_eq!(white, 1000. + K / 4.);
+    }
+
+    #[test]
+    fn expected_score_of_equal_ratings_is_half() {
+        assert_eq!(expected_score(1000., 1000.), 0.5);
+    }
+
+    #[test]
+    fn expected_score_of_200_point_gap_is_three_quarters() {
+        assert!((expected_score(1200., 1000.) - 0.75).abs() < 1e-6);
+        assert!((expected_score(1000., 1200.) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fide_scale_gives_ten_to_one_odds_at_400_points() {
+        let scale = 400. / f64::consts::LN_10;
+        let score = expected_score_with_scale(1400., 1000., scale);
+        assert!((score / (1. - score) - 10.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn predict_prints_expected_scores_and_odds() {
+        // 400 points is a 9:1 favourite on the default scale.
+        assert_eq!(
+            format_prediction("alice", 1400., "bob", 1000., RATING_CONST),
+            "Rating difference: +400\nalice\t90.0%\t1.11\t-900\nbob\t10.0%\t10.00\t+900\n"
+        );
+        assert_eq!(
+            format_prediction("alice", 1000., "bob", 1000., RATING_CONST),
+            "Rating difference: +0\nalice\t50.0%\t2.00\t-100\nbob\t50.0%\t2.00\t-100\n"
+        );
+
+        let path = temp_table("predict", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+        run(config(&["leelo", "predict", "alice", "bob", path_str])).unwrap();
+        let err = run(config(&["leelo", "predict", "alice", "bbo", path_str])).unwrap_err();
+        assert_eq!(err.to_string(), "player bbo not found. Did you mean 'bob'?");
+        remove_table(&path);
+    }
+
+    #[test]
+    fn fair_odds_of_favourite_and_underdog() {
+        let (decimal, moneyline) = fair_odds(0.75).unwrap();
+        assert!((decimal - 4. / 3.).abs() < 1e-9 && (moneyline + 300.).abs() < 1e-9);
+        let (decimal, moneyline) = fair_odds(0.25).unwrap();
+        assert!((decimal - 4.).abs() < 1e-9 && (moneyline - 300.).abs() < 1e-9);
+        assert_eq!(fair_odds(0.), None);
+        assert_eq!(fair_odds(1.), None);
+    }
+
+    #[test]
+    fn format_rating_precision() {
+        assert_eq!(format_rating(1012.5, 0), "1013");
+        assert_eq!(format_rating(1012.3456, 2), "1012.35");
+        assert_eq!(format_rating(-12.4, 0), "-12");
+    }
+
+    #[test]
+    fn new_ratings_between_equal_players() {
+        assert_eq!(
+            new_ratings(1000., 1000., MatchResult::WhiteWin),
+            (1020., 980.)
+        );
+        assert_eq!(
+            new_ratings(1000., 1000., MatchResult::BlackWin),
+            (980., 1020.)
+        );
+        assert_eq!(new_ratings(1000., 1000., MatchResult::Draw), (1000., 1000.));
+    }
+
+    #[test]
+    fn new_ratings_conserves_total_rating() {
+        let (white, black) = new_ratings(1300., 1000., MatchResult::BlackWin);
+        assert!((white + black - 2300.).abs() < 1e-9);
+        assert!(black > 1000.);
+    }
+
+    #[test]
+    fn format_timestamp_is_iso_8601() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951782400 + 3723), "2000-02-29T01:02:03Z");
+    }
+
+    #[test]
+    fn parse_timestamp_inverts_format_timestamp() {
+        for secs in [0, 951782400 + 3723, 1657028700] {
+            assert_eq!(parse_timestamp(&format_timestamp(secs)), Some(secs));
+        }
+        assert_eq!(parse_timestamp("2000-02-29"), Some(951782400));
+        assert_eq!(parse_timestamp("2000-13-01"), None);
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn decay_is_logged_replayed_and_undone() {
+        let path = temp_table("decay-history", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+        fs::write(
+            history_filename(path_str),
+            "White,Black,Result,Timestamp,White Rating,Black Rating,White RD,Black RD,Expected Score\n\
+             alice,bob,1-0,2020-01-01T00:00:00Z,1000,1000,350,350,0.5\n",
+        )
+        .unwrap();
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut played = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut played).unwrap();
+        assert!(played["alice"].rating() > INITIAL_RATING);
+
+        run(config(&["leelo", "decay", path_str])).unwrap();
+        let mut decayed = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut decayed).unwrap();
+        assert!(decayed["alice"].rating() < played["alice"].rating());
+        assert!(matches!(
+            read_history_records(path_str).unwrap().last(),
+            Some(HistoryRecord::Decay(decay)) if decay.player_ids == ["alice"]
+        ));
+
+        // Rebuilding keeps the decay rather than silently dropping it.
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut rebuilt = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut rebuilt).unwrap();
+        assert_eq!(rebuilt["alice"].white_rating, decayed["alice"].white_rating);
+        assert_eq!(rebuilt["alice"].black_rating, decayed["alice"].black_rating);
+
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut undone = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut undone).unwrap();
+        assert_eq!(undone["alice"].rating(), played["alice"].rating());
+        assert_eq!(undone["alice"].games, 1);
+        assert_eq!(read_history_records(path_str).unwrap().len(), 1);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn decay_only_affects_inactive_players_above_initial_rating() {
+        let entry = |white: &str, black: &str, timestamp: &str| HistoryEntry {
+            white_player_id: white.to_string(),
+            black_player_id: black.to_string(),
+            result: MatchResult::Draw,
+            timestamp: timestamp.to_string(),
+            white_rating: None,
+            black_rating: None,
+            white_rd: None,
+            black_rd: None,
+            expected_score: None,
+        };
+        let history = [
+            HistoryRecord::Game(entry("alice", "bob", "2022-01-01T00:00:00Z")),
+            HistoryRecord::Game(entry("carol", "dave", "2022-01-01T00:00:00Z")),
+            HistoryRecord::Game(entry("carol", "erin", "2022-06-01T00:00:00Z")),
+            HistoryRecord::TeamGame(TeamGame {
+                white_team: args(&["frank", "gina"]),
+                black_team: args(&["dave"]),
+                result: MatchResult::Draw,
+                timestamp: "2022-06-01T00:00:00Z".to_string(),
+                white_ratings: vec![1200., 1000.],
+                black_ratings: vec![1000.],
+            }),
+        ];
+        let mut data = HashMap::new();
+        for (player_id, rating) in [
+            ("alice", 1200.),
+            ("bob", 900.),
+            ("carol", 1200.),
+            ("frank", 1200.),
+        ] {
+            data.insert(player_id.to_string(), PlayerRecord::new(rating));
+        }
+
+        let now = parse_timestamp("2022-06-15").unwrap();
+        assert_eq!(
+            decay_ratings(&history, now, &mut data).player_ids,
+            ["alice"]
+        );
+        assert_eq!(data["alice"].rating(), 1200. - DECAY_RATE * 200.);
+        assert_eq!(data["bob"].rating(), 900.);
+        assert_eq!(data["carol"].rating(), 1200.);
+        assert_eq!(data["frank"].rating(), 1200.);
+    }
+
+    #[test]
+    fn game_is_appended_to_history() {
+        let path = temp_table("history", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        run(config(&[
+            "leelo", "game", "bob", "alice", "0.5-0.5", path_str,
+        ]))
+        .unwrap();
+
+        let history = read_history(path_str).unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].white_player_id, "alice");
+        assert_eq!(history[0].result, MatchResult::WhiteWin);
+        assert_eq!(history[1].white_player_id, "bob");
+        assert_eq!(history[1].result, MatchResult::Draw);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn undo_restores_ratings_before_last_game() {
+        let path = temp_table("undo", "Player ID,Rating\nalice,1000\nbob,1100\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        let mut after_first = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut after_first).unwrap();
+        run(config(&["leelo", "game", "bob", "alice", "1-0", path_str])).unwrap();
+
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        for player_id in ["alice", "bob"] {
+            assert_eq!(
+                data[player_id].white_rating,
+                after_first[player_id].white_rating
+            );
+            assert_eq!(
+                data[player_id].black_rating,
+                after_first[player_id].black_rating
+            );
+        }
+        assert_eq!(data["alice"].games, 1);
+        assert_eq!((data["bob"].wins, data["bob"].losses), (0, 1));
+        assert_eq!(read_history(path_str).unwrap().len(), 1);
+
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["alice"].rating(), 1000.);
+        assert_eq!(data["bob"].rating(), 1100.);
+        assert!(run(config(&["leelo", "undo", path_str])).is_err());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn game_only_updates_the_colors_played() {
+        let path = temp_table("colors", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert!(data["alice"].white_rating > 1000.);
+        assert_eq!(data["alice"].black_rating, 1000.);
+        assert_eq!(data["bob"].white_rating, 1000.);
+        assert!(data["bob"].black_rating < 1000.);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn k_flag_overrides_k_factor() {
+        let path = temp_table("k-flag", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo", "game", "alice", "bob", "1-0", "--k", "10", path_str,
+        ]))
+        .unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["alice"].white_rating, 1005.);
+        assert_eq!(data["bob"].black_rating, 995.);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn margin_scales_rating_change() {
+        let white_change = |margin| {
+            let mut data = HashMap::new();
+            data.insert("alice".to_string(), PlayerRecord::new(1000.));
+            data.insert("bob".to_string(), PlayerRecord::new(1000.));
+            let options = RatingOptions {
+                k: Some(40.),
+                margin,
+                ..RatingOptions::default()
+            };
+            let change = update_ratings(
+                "alice".into(),
+                "bob".into(),
+                MatchResult::WhiteWin,
+                &options,
+                &mut data,
+            )
+            .unwrap();
+            change.white_after - change.white_before
+        };
+
+        assert_eq!(white_change(None), 20.);
+        assert_eq!(white_change(Some(0.)), 20.);
+        assert!((white_change(Some(3.)) - 20. * margin_multiplier(3.)).abs() < 1e-9);
+        assert!(margin_multiplier(3.) > margin_multiplier(1.));
+    }
+
+    #[test]
+    fn k_flag_rejects_invalid_values() {
+        for k in ["abc", "-5"] {
+            assert!(Config::new(&args(&[
+                "leelo", "game", "a", "b", "1-0", "--k", k, "f.csv"
+            ]))
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn initial_flag_sets_starting_rating() {
+        let path = temp_table("initial", "Player ID,Rating,Games\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo",
+            "player",
+            "alice",
+            "--initial",
+            "1200",
+            path_str,
+        ]))
+        .unwrap();
+        run(config(&["leelo", "player", "bob", path_str])).unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["alice"].rating(), 1200.);
+        assert_eq!(data["bob"].rating(), INITIAL_RATING);
+        assert!(Config::new(&args(&[
+            "leelo",
+            "player",
+            "carol",
+            "--initial",
+            "inf",
+            path_str
+        ]))
+        .is_err());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn rating_flag_sets_starting_rating() {
+        let path = temp_table("rating-flag", "Player ID,Rating,Games\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo", "player", "carol", "--rating", "1850", path_str,
+        ]))
+        .unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["carol"].rating(), 1850.);
+        for bad in [
+            &["leelo", "player", "dave", "--rating", "nan", path_str][..],
+            &[
+                "leelo",
+                "player",
+                "dave",
+                "--rating",
+                "1",
+                "--initial",
+                "1",
+                path_str,
+            ],
+            &[
+                "leelo", "player", "--from", "ids.txt", "--rating", "1", path_str,
+            ],
+        ] {
+            assert!(Config::new(&args(bad)).is_err());
+        }
+        remove_table(&path);
+    }
+
+    #[test]
+    fn view_sorts_nan_ratings_last() {
+        let path = temp_table("nan", "Player ID,Rating\nalice,nan\nbob,1100\ncarol,900\n");
+        let path_str = path.to_str().unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        let order = |sort_order| -> Vec<String> {
+            sort_standings(&data, sort_order)
+                .iter()
+                .map(|(id, _)| id.to_string())
+                .collect()
+        };
+        assert_eq!(order(SortOrder::RatingDesc), ["bob", "carol", "alice"]);
+        assert_eq!(order(SortOrder::RatingAsc), ["carol", "bob", "alice"]);
+        assert!(run(config(&["leelo", "view", path_str])).is_ok());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn failed_write_leaves_original_file_intact() {
+        let contents = "Player ID,Rating,Games\nalice,1000,0\n";
+        let path = temp_table("atomic", contents);
+        let path_str = path.to_str().unwrap();
+
+        let result = write_atomically(path_str, |file| {
+            file.write_all(b"Player ID,Rat")?;
+            Err("simulated failure".into())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+        assert!(!Path::new(&format!("{}.tmp", path_str)).exists());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn sort_by_name_ignores_case() {
+        let mut data = HashMap::new();
+        for player_id in ["bob", "Carol", "alice"] {
+            data.insert(player_id.to_string(), PlayerRecord::new(1000.));
+        }
+
+        let order: Vec<&str> = sort_standings(&data, SortOrder::Name)
+            .iter()
+            .map(|(id, _)| id.as_str())
+            .collect();
+        assert_eq!(order, ["alice", "bob", "Carol"]);
+        assert!(SortOrder::parse("elo").is_err());
+    }
+
+    #[test]
+    fn standings_json_keeps_full_precision() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1012.75));
+        data.insert("bob".to_string(), PlayerRecord::new(987.25));
+
+        let json = standings_json(&sort_standings(&data, SortOrder::RatingDesc));
+        assert_eq!(
+            json.to_string(),
+            "[{\"black_rating\":1012.75,\"player_id\":\"alice\",\"rating\":1012.75,\"white_rating\":1012.75},\
+             {\"black_rating\":987.25,\"player_id\":\"bob\",\"rating\":987.25,\"white_rating\":987.25}]"
+        );
+    }
+
+    #[test]
+    fn import_applies_games_in_order() {
+        let path = temp_table("import", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+        let games = temp_table("import-games", "alice,bob,1-0\nbob,alice,0.5-0.5\n");
+
+        run(config(&[
+            "leelo",
+            "import",
+            games.to_str().unwrap(),
+            path_str,
+        ]))
+        .unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        let mut expected = HashMap::new();
+        expected.insert("alice".to_string(), PlayerRecord::new(1000.));
+        expected.insert("bob".to_string(), PlayerRecord::new(1000.));
+        update_ratings(
+            "alice".into(),
+            "bob".into(),
+            MatchResult::WhiteWin,
+            &RatingOptions::default(),
+            &mut expected,
+        )
+        .unwrap();
+        update_ratings(
+            "bob".into(),
+            "alice".into(),
+            MatchResult::Draw,
+            &RatingOptions::default(),
+            &mut expected,
+        )
+        .unwrap();
+        assert_eq!(data["alice"].rating(), expected["alice"].rating());
+        assert_eq!(data["bob"].rating(), expected["bob"].rating());
+        assert_eq!(read_history(path_str).unwrap().len(), 2);
+        remove_table(&games);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn import_with_unknown_player_writes_nothing() {
+        let contents = "Player ID,Rating\nalice,1000\nbob,1000\n";
+        let path = temp_table("import-unknown", contents);
+        let path_str = path.to_str().unwrap();
+        let games = temp_table("import-unknown-games", "alice,bob,1-0\nalice,zed,0-1\n");
+
+        let result = run(config(&[
+            "leelo",
+            "import",
+            games.to_str().unwrap(),
+            path_str,
+        ]));
+
+        assert!(result.unwrap_err().to_string().starts_with("line 2:"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+        remove_table(&games);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn dry_run_leaves_file_unchanged() {
+        let contents = "Player ID,Rating\nalice,1000\nbob,1000\n";
+        let path = temp_table("dry-run", contents);
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo",
+            "game",
+            "alice",
+            "bob",
+            "1-0",
+            "--dry-run",
+            path_str,
+        ]))
+        .unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+        assert!(read_history(path_str).is_err());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn head_to_head_counts_both_colors() {
+        let entry = |white: &str, black: &str, result| HistoryEntry {
+            white_player_id: white.to_string(),
+            black_player_id: black.to_string(),
+            result,
+            timestamp: String::new(),
+            white_rating: None,
+            black_rating: None,
+            white_rd: None,
+            black_rd: None,
+            expected_score: None,
+        };
+        let history = [
+            entry("alice", "bob", MatchResult::WhiteWin),
+            entry("bob", "alice", MatchResult::WhiteWin),
+            entry("bob", "alice", MatchResult::BlackWin),
+            entry("alice", "carol", MatchResult::WhiteWin),
+            entry("alice", "bob", MatchResult::Draw),
+        ];
+
+        assert_eq!(head_to_head("alice", "bob", &history), (2, 1, 1));
+        assert_eq!(head_to_head("bob", "carol", &history), (0, 0, 0));
+    }
+
+    #[test]
+    fn glicko_update_matches_reference_values() {
+        let (rating, rd) = glicko_update(1500., 200., 1400., 30., 1.);
+        assert!((rating - 1563.4320485812902).abs() < 1e-9);
+        assert!((rd - 175.22023356952306).abs() < 1e-9);
+        let (rating, rd) = glicko_update(1400., 30., 1500., 200., 0.);
+        assert!((rating - 1398.342512471733).abs() < 1e-9);
+        assert!((rd - 29.925091041592754).abs() < 1e-9);
+    }
+
+    #[test]
+    fn glicko_game_shrinks_rd_and_can_be_undone() {
+        let path = temp_table("glicko", "Player ID,Rating\nalice,1500\nbob,1500\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo", "game", "alice", "bob", "1-0", "--system", "glicko", path_str,
+        ]))
+        .unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert!(data["alice"].white_rating > 1500.);
+        assert!(data["alice"].rd < GLICKO_INITIAL_RD);
+        assert!(data["bob"].rd < GLICKO_INITIAL_RD);
+
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["alice"].white_rating, 1500.);
+        assert_eq!(data["alice"].rd, GLICKO_INITIAL_RD);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn player_ids_that_would_break_csv_are_rejected() {
+        let mut data = HashMap::new();
+
+        for player_id in ["a,b", "a\"b", "a\nb", ""] {
+            assert!(create_player(player_id.to_string(), INITIAL_RATING, &mut data).is_err());
+        }
+        assert!(data.is_empty());
+
+        create_player("alice".to_string(), INITIAL_RATING, &mut data).unwrap();
+        assert!(rename_player("alice".to_string(), "a,b".to_string(), &mut data).is_err());
+        assert!(data.contains_key("alice"));
+    }
+
+    #[test]
+    fn reset_keeps_games_unless_cleared() {
+        let mut data = HashMap::new();
+        let mut player = PlayerRecord::new(1300.);
+        player.add_game(1.);
+        data.insert("alice".to_string(), player);
+
+        reset_player("alice".to_string(), false, &mut data).unwrap();
+        assert_eq!(data["alice"].rating(), INITIAL_RATING);
+        assert_eq!((data["alice"].games, data["alice"].wins), (1, 1));
+
+        reset_player("alice".to_string(), true, &mut data).unwrap();
+        assert_eq!((data["alice"].games, data["alice"].wins), (0, 0));
+        assert!(reset_player("bob".to_string(), false, &mut data).is_err());
+    }
+
+    #[test]
+    fn rating_change_summary() {
+        let change = RatingChange {
+            white_before: 1000.,
+            white_after: 1012.2,
+            black_before: 1000.,
+            black_after: 987.8,
+        };
+        assert_eq!(
+            format_rating_change(&change),
+            "White: 1000 -> 1012 (+12), Black: 1000 -> 988 (-12)"
+        );
+    }
+
+    #[test]
+    fn standings_csv_keeps_full_precision() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1012.75));
+        data.insert("bob".to_string(), PlayerRecord::new(987.125));
+
+        let mut output = Vec::new();
+        write_standings_csv(&sort_standings(&data, SortOrder::RatingDesc), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Player ID,Rating\nalice,1012.75\nbob,987.125\n"
+        );
+    }
+
+    #[test]
+    fn sqlite_store_round_trips_players() {
+        let path = env::temp_dir().join(format!("leelo-sqlite-{}.db", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let _ = fs::remove_file(&path);
+
+        run(config(&["leelo", "new", path_str])).unwrap();
+        run(config(&["leelo", "player", "alice", path_str])).unwrap();
+        run(config(&["leelo", "player", "bob", path_str])).unwrap();
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+
+        let mut data = HashMap::new();
+        open_store(path_str, &StoreOptions::default())
+            .read(&mut data)
+            .unwrap();
+        assert_eq!(data.len(), 2);
+        assert!(data["alice"].white_rating > INITIAL_RATING);
+        assert_eq!((data["alice"].games, data["alice"].wins), (1, 1));
+        assert_eq!(data["bob"].losses, 1);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn sqlite_store_does_not_create_missing_tables_on_read() {
+        let path = env::temp_dir().join(format!("leelo-missing-{}.db", std::process::id()));
+
+        let mut data = HashMap::new();
+        assert!(open_store(path.to_str().unwrap(), &StoreOptions::default())
+            .read(&mut data)
+            .is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn merge_resolves_overlapping_players() {
+        let table = |players: &[(&str, f64)]| -> HashMap<String, PlayerRecord> {
+            players
+                .iter()
+                .map(|(id, rating)| (id.to_string(), PlayerRecord::new(*rating)))
+                .collect()
+        };
+        let a = || table(&[("alice", 1100.), ("bob", 1000.)]);
+        let b = || table(&[("alice", 1300.), ("carol", 900.)]);
+
+        let mut merged = a();
+        assert_eq!(merge_tables(&mut merged, b(), ConflictPolicy::Average), 1);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged["alice"].rating(), 1200.);
+
+        let mut merged = a();
+        merge_tables(&mut merged, b(), ConflictPolicy::KeepA);
+        assert_eq!(merged["alice"].rating(), 1100.);
+
+        let mut merged = a();
+        merge_tables(&mut merged, b(), ConflictPolicy::KeepB);
+        assert_eq!(merged["alice"].rating(), 1300.);
+    }
+
+    #[test]
+    fn errors_map_to_exit_codes() {
+        let contents = "Player ID,Rating\nalice,1000\n";
+        let path = temp_table("exit-codes", contents);
+        let path_str = path.to_str().unwrap();
+        let missing = env::temp_dir().join("leelo-does-not-exist.csv");
+
+        let err = Config::new(&args(&["leelo", "game", "alice"]))
+            .err()
+            .unwrap();
+        assert_eq!(err.exit_code(), 2);
+        let err = run(config(&["leelo", "view", missing.to_str().unwrap()])).unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "no table at {0}. Create one with `leelo new {0}`.",
+                missing.display()
+            )
+        );
+        let err = run(config(&["leelo", "remove", "bob", path_str])).unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn remove_missing_player_errors_and_leaves_file_unchanged() {
+        let contents = "Player ID,Rating\nalice,1000.0\n";
+        let path = temp_table("remove-missing", contents);
+        let path_str = path.to_str().unwrap();
+
+        let result = run(config(&["leelo", "remove", "bob", path_str]));
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn rename_preserves_rating_exactly() {
+        let path = temp_table("rename", "Player ID,Rating\nalice,1012.3456789012345\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "rename", "alice", "carol", path_str])).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "Player ID,Rating,Games,Wins,Losses,Draws,White Rating,Black Rating,RD,Version,Last Played\n\
+             carol,1012.3456789012345,0,0,0,0,1012.3456789012345,1012.3456789012345,350.0,3,\n"
+        );
+        remove_table(&path);
+    }
+
+    #[test]
+    fn rename_carries_the_player_history() {
+        let path = temp_table(
+            "rename-history",
+            "Player ID,Rating\nalice,1000\nbob,1000\ncarol,1000\n",
+        );
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        run(config(&["leelo", "adjust", "alice", "+5", path_str])).unwrap();
+        run(config(&[
+            "leelo",
+            "game",
+            "--team",
+            "alice,carol",
+            "bob",
+            "0-1",
+            path_str,
+        ]))
+        .unwrap();
+        run(config(&[
+            "leelo", "game", "bob", "alice", "1/2-1/2", path_str,
+        ]))
+        .unwrap();
+        let mut played = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut played).unwrap();
+
+        run(config(&["leelo", "rename", "alice", "alicia", path_str])).unwrap();
+        let history = read_history_records(path_str).unwrap();
+        assert!(history
+            .iter()
+            .all(|record| !record_player_ids(record).contains(&"alice")));
+        assert_eq!(
+            history
+                .iter()
+                .filter(|record| record_player_ids(record).contains(&"alicia"))
+                .count(),
+            4
+        );
+
+        // Rebuilding replays every record instead of skipping alicia's games.
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut rebuilt = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut rebuilt).unwrap();
+        assert_eq!(rebuilt["alicia"].rating(), played["alice"].rating());
+        assert_eq!(rebuilt["alicia"].games, 3);
+
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut undone = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut undone).unwrap();
+        assert_eq!(undone["alicia"].games, 2);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn game_increments_games_for_both_players() {
+        let path = temp_table("games", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        run(config(&[
+            "leelo", "game", "bob", "alice", "0.5-0.5", path_str,
+        ]))
+        .unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["alice"].games, 2);
+        assert_eq!(data["bob"].games, 2);
+        assert_eq!((data["alice"].wins, data["alice"].draws), (1, 1));
+        assert_eq!((data["bob"].losses, data["bob"].draws), (1, 1));
+        remove_table(&path);
+    }
+
+    #[test]
+    fn provisional_player_moves_twice_as_far() {
+        let path = temp_table(
+            "provisional",
+            "Player ID,Rating,Games\nalice,1000,0\nbob,1000,20\n",
+        );
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        let alice_change = data["alice"].white_rating - 1000.;
+        let bob_change = data["bob"].black_rating - 1000.;
+        assert!((alice_change + 2. * bob_change).abs() < 1e-9);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn duplicate_player_id_is_rejected() {
+        let path = temp_table("duplicate", "Player ID,Rating\nalice,1000\nalice,1100\n");
+
+        let mut data = HashMap::new();
+        let err = read_to_hashmap(path.to_str().unwrap(), true, b',', &mut data).unwrap_err();
+        assert_eq!(err.to_string(), "duplicate player id alice in table.");
+        remove_table(&path);
+    }
+
+    #[test]
+    fn markdown_standings_table() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1012.4));
+        data.insert("b|ob".to_string(), PlayerRecord::new(987.6));
+
+        let mut out = Vec::new();
+        write_standings_markdown(&sort_standings(&data, SortOrder::RatingDesc), &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "| Player ID | Rating |\n| --- | --- |\n| alice | 1012 |\n| b\\|ob | 988 |\n"
+        );
+    }
+
+    #[test]
+    fn tied_players_share_a_rank() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1100.));
+        data.insert("bob".to_string(), PlayerRecord::new(1000.));
+        data.insert("carol".to_string(), PlayerRecord::new(1000.));
+        data.insert("dave".to_string(), PlayerRecord::new(900.));
+
+        let data_vec = sort_standings(&data, SortOrder::RatingDesc);
+        assert_eq!(standings_ranks(&data_vec), vec![1, 2, 2, 4]);
+        let data_vec = sort_standings(&data, SortOrder::Name);
+        assert_eq!(standings_ranks(&data_vec), vec![1, 2, 2, 4]);
+    }
+
+    #[test]
+    fn create_players_skips_existing_ids() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1200.));
+
+        let skipped =
+            create_players(args(&["alice", "bob", "carol", "bob"]), 1000., &mut data).unwrap();
+        assert_eq!(skipped, args(&["alice", "bob"]));
+        assert_eq!(data.len(), 3);
+        assert_eq!(data["alice"].rating(), 1200.);
+        assert_eq!(data["carol"].rating(), 1000.);
+    }
+
+    #[test]
+    fn version_flag_is_recognised() {
+        for flag in ["--version", "-V", "version"] {
+            assert!(matches!(
+                config(&["leelo", flag]).operation,
+                Operation::Version
+            ));
+        }
+        assert!(matches!(
+            config(&["leelo", "--quiet", "--version"]).operation,
+            Operation::Version
+        ));
+    }
+
+    #[test]
+    fn usage_errors_name_the_missing_argument() {
+        let missing = |argv: &[&str]| Config::new(&args(argv)).err().unwrap().to_string();
+        assert_eq!(
+            missing(&["leelo", "show"]),
+            "missing <player_id> for `show`. Usage: leelo show <player_id> <file>."
+        );
+        assert_eq!(
+            missing(&["leelo", "show", "alice"]),
+            "missing <file> for `show`. Usage: leelo show <player_id> <file>."
+        );
+        assert_eq!(
+            missing(&["leelo", "predict", "alice"]),
+            "missing <player_b> for `predict`. Usage: leelo predict <player_a> <player_b> <file>."
+        );
+        assert_eq!(
+            missing(&["leelo", "import"]),
+            "missing <games_filename> for `import`. Usage: leelo import <games_filename> <file>."
+        );
+        // Flag values such as <ids_filename> aren't positional, so the file is what's missing.
+        assert!(missing(&["leelo", "player", "--from", "ids.txt"])
+            .starts_with("missing <file> for `player`."));
+    }
+
+    #[test]
+    fn argument_errors_name_the_command() {
+        let err = Config::new(&args(&["leelo", "rename", "alice"]))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "missing <new_id> for `rename`. Usage: leelo rename <old_id> <new_id> <file>."
+        );
+        let err = Config::new(&args(&["leelo", "game", "alice", "bob", "1-0"]))
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("missing <file> for `game`."));
+        let err = Config::new(&args(&["leelo", "undo", "--force", "t.csv"]))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "unknown flag --force for `undo`.");
+    }
+
+    #[test]
+    fn round_robin_pairs_everyone_once() {
+        for n in 1..8 {
+            let ids: Vec<String> = (0..n).map(|i| format!("p{}", i)).collect();
+            let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+            let rounds = round_robin(&ids);
+
+            let mut games = Vec::new();
+            for round in &rounds {
+                let mut seen: Vec<&str> = Vec::new();
+                for (white, black) in round {
+                    seen.push(white);
+                    if let Some(black) = black {
+                        seen.push(black);
+                        let mut pair = [*white, *black];
+                        pair.sort();
+                        games.push(pair);
+                    }
+                }
+                seen.sort();
+                assert_eq!(seen, ids);
+            }
+            games.sort();
+            games.dedup();
+            assert_eq!(games.len(), n * (n - 1) / 2);
+        }
+    }
+
+    fn game(white: &str, black: &str, result: MatchResult) -> HistoryEntry {
+        HistoryEntry {
+            white_player_id: white.to_string(),
+            black_player_id: black.to_string(),
+            result,
+            timestamp: String::new(),
+            white_rating: None,
+            black_rating: None,
+            white_rd: None,
+            black_rd: None,
+            expected_score: None,
+        }
+    }
+
+    #[test]
+    fn swiss_pairs_by_score_without_rematches() {
+        let standings = ["a", "b", "c", "d", "e"];
+        let history = [
+            game("a", "b", MatchResult::BlackWin),
+            game("c", "d", MatchResult::WhiteWin),
+        ];
+
+        // b and c lead on a point each. They haven't met, so they play, with b
+        // taking White after playing Black. e is ranked last on zero points and gets the bye.
+        assert_eq!(
+            swiss_pairings(&standings, &history),
+            vec![("b", Some("c")), ("d", Some("a")), ("e", None)]
+        );
+
+        // Once b and c have met, each is paired down instead of facing the other again.
+        let history = [
+            game("a", "b", MatchResult::BlackWin),
+            game("c", "d", MatchResult::WhiteWin),
+            game("b", "c", MatchResult::Draw),
+            game("a", "d", MatchResult::Draw),
+        ];
+        let pairings = swiss_pairings(&standings, &history);
+        assert!(!pairings.contains(&("b", Some("c"))));
+        assert!(!pairings.contains(&("c", Some("b"))));
+    }
+
+    #[test]
+    fn team_game_rates_each_player_against_the_other_team() {
+        let mut data = HashMap::new();
+        for (player_id, rating) in [("a", 1100.), ("b", 900.), ("c", 1000.), ("d", 1000.)] {
+            let mut player = PlayerRecord::new(rating);
+            player.games = PROVISIONAL_GAMES;
+            data.insert(player_id.to_string(), player);
+        }
+
+        let changes = update_team_ratings(
+            &args(&["a", "b"]),
+            &args(&["c", "d"]),
+            MatchResult::WhiteWin,
+            &RatingOptions::default(),
+            &mut data,
+        )
+        .unwrap();
+        assert_eq!(changes.len(), 4);
+        assert!((data["c"].black_rating - 980.).abs() < 1e-9);
+        assert!((data["d"].black_rating - 980.).abs() < 1e-9);
+        assert!(data["b"].white_rating - 900. > data["a"].white_rating - 1100.);
+        assert_eq!((data["a"].wins, data["c"].losses), (1, 1));
+
+        let result = update_team_ratings(
+            &args(&["a", "b"]),
+            &args(&["b", "c"]),
+            MatchResult::Draw,
+            &RatingOptions::default(),
+            &mut data,
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn team_games_are_logged_replayed_and_undone() {
+        let path = temp_table("team-history", "Player ID,Rating\na,1000\nb,1000\nc,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "a", "b", "1-0", path_str])).unwrap();
+        let mut before = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut before).unwrap();
+        run(config(&[
+            "leelo", "game", "--team", "a,b", "c", "0-1", path_str,
+        ]))
+        .unwrap();
+        let mut played = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut played).unwrap();
+        let history = read_history_records(path_str).unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(matches!(
+            &history[1],
+            HistoryRecord::TeamGame(game) if game.white_team == ["a", "b"] && game.black_ratings == [1000.]
+        ));
+        assert_eq!(read_history(path_str).unwrap().len(), 1);
+
+        // Rebuilding replays the team game rather than dropping it.
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut rebuilt = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut rebuilt).unwrap();
+        for player_id in ["a", "b", "c"] {
+            assert_eq!(rebuilt[player_id].rating(), played[player_id].rating());
+            assert_eq!(rebuilt[player_id].games, played[player_id].games);
+        }
+        assert_eq!(read_history_records(path_str).unwrap().len(), 2);
+
+        // Undoing it restores the ratings after the first game, and each player's last game.
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut undone = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut undone).unwrap();
+        let first = &read_history(path_str).unwrap()[0];
+        for player_id in ["a", "b", "c"] {
+            assert_eq!(
+                undone[player_id].white_rating,
+                before[player_id].white_rating
+            );
+            assert_eq!(
+                undone[player_id].black_rating,
+                before[player_id].black_rating
+            );
+            assert_eq!(undone[player_id].games, before[player_id].games);
+        }
+        assert_eq!(
+            undone["a"].last_played.as_deref(),
+            Some(first.timestamp.as_str())
+        );
+        assert_eq!(undone["c"].last_played, None);
+        assert_eq!(read_history_records(path_str).unwrap().len(), 1);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn free_for_all_games_are_logged_replayed_and_undone() {
+        let path = temp_table("ffa-history", "Player ID,Rating\na,1000\nb,1000\nc,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "game", "a", "b", "1-0", path_str])).unwrap();
+        let mut before = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut before).unwrap();
+        run(config(&["leelo", "ffa", "c", "b", "a", path_str])).unwrap();
+        let mut played = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut played).unwrap();
+        let history = read_history_records(path_str).unwrap();
+        assert!(matches!(
+            &history[1],
+            HistoryRecord::FreeForAll(game) if game.ranking == ["c", "b", "a"]
+        ));
+
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut rebuilt = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut rebuilt).unwrap();
+        for player_id in ["a", "b", "c"] {
+            assert_eq!(
+                rebuilt[player_id].white_rating,
+                played[player_id].white_rating
+            );
+            assert_eq!(
+                rebuilt[player_id].black_rating,
+                played[player_id].black_rating
+            );
+            assert_eq!(rebuilt[player_id].games, played[player_id].games);
+        }
+
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut undone = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut undone).unwrap();
+        for player_id in ["a", "b", "c"] {
+            assert_eq!(
+                undone[player_id].white_rating,
+                before[player_id].white_rating
+            );
+            assert_eq!(
+                undone[player_id].black_rating,
+                before[player_id].black_rating
+            );
+            assert_eq!(
+                (undone[player_id].wins, undone[player_id].losses),
+                (before[player_id].wins, before[player_id].losses)
+            );
+        }
+        assert_eq!(undone["c"].last_played, None);
+        assert_eq!(read_history_records(path_str).unwrap().len(), 1);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn table_stats_summarises_ratings() {
+        assert!(table_stats(&HashMap::new()).is_none());
+
+        let mut data = HashMap::new();
+        for (player_id, rating) in [("a", 1100.), ("b", 900.), ("c", 1000.), ("d", 1200.)] {
+            data.insert(player_id.to_string(), PlayerRecord::new(rating));
+        }
+        let stats = table_stats(&data).unwrap();
+        assert_eq!(stats.players, 4);
+        assert_eq!((stats.mean, stats.median), (1050., 1050.));
+        assert!((stats.std_dev - 125f64.sqrt() * 10.).abs() < 1e-9);
+        assert_eq!(stats.highest, ("d".to_string(), 1200.));
+        assert_eq!(stats.lowest, ("b".to_string(), 900.));
+    }
+
+    #[test]
+    fn player_cannot_play_themselves() {
+        let path = temp_table("self-play", "Player ID,Rating\nalice,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        let err = run(config(&[
+            "leelo", "game", "alice", "alice", "1-0", path_str,
+        ]))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "a player cannot play against themselves.");
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!((data["alice"].rating(), data["alice"].games), (1000., 0));
+        remove_table(&path);
+    }
+
+    #[test]
+    fn new_ratings_are_clamped() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1195.));
+        data.insert("bob".to_string(), PlayerRecord::new(805.));
+        let options = RatingOptions {
+            floor: Some(800.),
+            ceiling: Some(1200.),
+            ..RatingOptions::default()
+        };
+
+        let change = update_ratings(
+            "alice".into(),
+            "bob".into(),
+            MatchResult::WhiteWin,
+            &options,
+            &mut data,
+        )
+        .unwrap();
+        assert_eq!((change.white_after, change.black_after), (1200., 800.));
+        assert!(Config::new(&args(&[
+            "leelo",
+            "game",
+            "a",
+            "b",
+            "1-0",
+            "--floor",
+            "900",
+            "--ceiling",
+            "800",
+            "t.csv"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn writes_back_up_the_previous_table() {
+        let path = temp_table("backup", "Player ID,Rating\nalice,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "player", "bob", path_str])).unwrap();
+        assert_eq!(
+            fs::read_to_string(backup_filename(path_str)).unwrap(),
+            "Player ID,Rating\nalice,1000\n"
+        );
+
+        fs::remove_file(backup_filename(path_str)).unwrap();
+        run(config(&[
+            "leelo",
+            "--no-backup",
+            "player",
+            "carol",
+            path_str,
+        ]))
+        .unwrap();
+        assert!(!Path::new(&backup_filename(path_str)).exists());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn ratings_round_trip_exactly() {
+        let path = temp_table("precision", "");
+        let path_str = path.to_str().unwrap();
+        let ratings = [
+            0.1 + 0.2,
+            1000. / 3.,
+            1e-300,
+            1234.5678901234567,
+            -f64::EPSILON,
+        ];
+
+        let mut data = HashMap::new();
+        for (i, rating) in ratings.iter().enumerate() {
+            let mut player = PlayerRecord::new(*rating);
+            player.black_rating = rating * 7.;
+            player.rd = rating / 7.;
+            data.insert(format!("p{}", i), player);
+        }
+        write_to_csv(path_str, true, b',', &data).unwrap();
+
+        let mut read = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut read).unwrap();
+        for (player_id, player) in &data {
+            let read = &read[player_id];
+            assert_eq!(read.white_rating.to_bits(), player.white_rating.to_bits());
+            assert_eq!(read.black_rating.to_bits(), player.black_rating.to_bits());
+            assert_eq!(read.rd.to_bits(), player.rd.to_bits());
+        }
+        remove_table(&path);
+    }
+
+    #[test]
+    fn locked_table_is_busy() {
+        let path = temp_table("lock", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        let lock = TableLock::acquire(path_str).unwrap();
+        let err = run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap_err();
+        assert!(err.to_string().starts_with("table is busy."));
+        // Every command that rewrites the table waits for the lock, not just games.
+        for command in [
+            &["leelo", "undo", path_str][..],
+            &["leelo", "import", "games.csv", path_str],
+            &["leelo", "decay", path_str],
+            &["leelo", "reset", "alice", path_str],
+            &["leelo", "rename", "alice", "carol", path_str],
+            &["leelo", "remove", "alice", path_str],
+            &["leelo", "merge", path_str, path_str, path_str],
+        ] {
+            let err = run(config(command)).unwrap_err();
+            assert!(
+                err.to_string().starts_with("table is busy."),
+                "{:?}",
+                command
+            );
+        }
+        drop(lock);
+
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        assert!(!Path::new(&format!("{}.lock", path_str)).exists());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn rebuild_reproduces_current_ratings() {
+        let path = temp_table("rebuild", "");
+        let path_str = path.to_str().unwrap();
+        run(config(&["leelo", "new", path_str, "--force"])).unwrap();
+        for player_id in ["alice", "bob", "carol"] {
+            run(config(&["leelo", "player", player_id, path_str])).unwrap();
+        }
+        for (white, black, result) in [
+            ("alice", "bob", "1-0"),
+            ("bob", "carol", "draw"),
+            ("carol", "alice", "1-0"),
+            ("alice", "bob", "0-1"),
+        ] {
+            run(config(&["leelo", "game", white, black, result, path_str])).unwrap();
+        }
+        let mut before = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut before).unwrap();
+
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut after = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut after).unwrap();
+        for (player_id, player) in &before {
+            assert!((after[player_id].white_rating - player.white_rating).abs() < 1e-9);
+            assert!((after[player_id].black_rating - player.black_rating).abs() < 1e-9);
+            assert_eq!(after[player_id].games, player.games);
+        }
+
+        // The rewritten history can still be undone.
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        remove_table(&path);
+    }
+
+    #[test]
+    fn rebuild_keeps_seeded_starting_ratings() {
+        let path = temp_table("rebuild-seeded", "Player ID,Rating\n");
+        let path_str = path.to_str().unwrap();
+        run(config(&[
+            "leelo",
+            "player",
+            "alice",
+            "--initial",
+            "1200",
+            path_str,
+        ]))
+        .unwrap();
+        run(config(&["leelo", "player", "bob", path_str])).unwrap();
+        run(config(&[
+            "leelo", "player", "carol", "--rating", "1850", path_str,
+        ]))
+        .unwrap();
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        run(config(&["leelo", "game", "bob", "alice", "0-1", path_str])).unwrap();
+        let mut before = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut before).unwrap();
+        let played = read_history(path_str).unwrap();
+
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut after = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut after).unwrap();
+        for player_id in ["alice", "bob", "carol"] {
+            assert_eq!(
+                after[player_id].white_rating,
+                before[player_id].white_rating
+            );
+            assert_eq!(
+                after[player_id].black_rating,
+                before[player_id].black_rating
+            );
+        }
+        // The pre-game ratings written back are the ones the games were played at, so undo
+        // still restores the right values.
+        let rebuilt = read_history(path_str).unwrap();
+        assert_eq!(played[0].white_rating, Some(1200.));
+        for (entry, original) in rebuilt.iter().zip(&played) {
+            assert_eq!(entry.white_rating, original.white_rating);
+            assert_eq!(entry.black_rating, original.black_rating);
+        }
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut undone = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut undone).unwrap();
+        assert_eq!(undone["bob"].white_rating, played[1].white_rating.unwrap());
+        assert_eq!(
+            undone["alice"].black_rating,
+            played[1].black_rating.unwrap()
+        );
+        remove_table(&path);
+    }
+
+    #[test]
+    fn output_format_flags() {
+        let format = |values: &[&str]| parse_output_format(&mut args(values));
+        assert_eq!(format(&["view", "t.csv"]).unwrap(), OutputFormat::Text);
+        assert_eq!(
+            format(&["view", "--format", "md", "t.csv"]).unwrap(),
+            OutputFormat::Markdown
+        );
+        assert_eq!(
+            format(&["view", "--json", "t.csv"]).unwrap(),
+            OutputFormat::Json
+        );
+        assert!(format(&["view", "--json", "--format", "csv", "t.csv"]).is_err());
+        assert!(format(&["view", "--format", "xml", "t.csv"]).is_err());
+    }
+
+    #[test]
+    fn list_prints_player_ids_by_name() {
+        let path = temp_table(
+            "list",
+            "Player ID,Rating\ncarol,1200\nalice,1000\nbob,1100\n",
+        );
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "list", path_str])).unwrap();
+        run(config(&["leelo", "l", "--json", path_str])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        let ids: Vec<&String> = sort_standings(&data, SortOrder::Name)
+            .into_iter()
+            .map(|(player_id, _)| player_id)
+            .collect();
+        assert_eq!(ids, ["alice", "bob", "carol"]);
+        remove_table(&path);
+        assert!(run(config(&["leelo", "list", path_str])).is_err());
+    }
+
+    #[test]
+    fn player_ids_render_in_every_format() {
+        let (alice, bob) = ("alice".to_string(), "bob".to_string());
+        let render = |format| {
+            let mut out = Vec::new();
+            render_player_ids(format, &[&alice, &bob], &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(OutputFormat::Text), "alice\nbob\n");
+        assert_eq!(render(OutputFormat::Csv), "Player ID\nalice\nbob\n");
+        assert_eq!(render(OutputFormat::Json), "[\"alice\",\"bob\"]\n");
+        assert_eq!(
+            render(OutputFormat::Markdown),
+            "| Player ID |\n| --- |\n| alice |\n| bob |\n"
+        );
+        assert!(
+            render(OutputFormat::Html).contains("<tr><td>alice</td></tr>\n<tr><td>bob</td></tr>")
+        );
+    }
+
+    #[test]
+    fn close_draws_can_leave_ratings_unchanged() {
+        let options = RatingOptions {
+            no_draw_change: Some(50.),
+            ..RatingOptions::default()
+        };
+        let draw = |white: f64, black: f64| {
+            let mut data = HashMap::new();
+            data.insert("alice".to_string(), PlayerRecord::new(white));
+            data.insert("bob".to_string(), PlayerRecord::new(black));
+            let change = update_ratings(
+                "alice".into(),
+                "bob".into(),
+                MatchResult::Draw,
+                &options,
+                &mut data,
+            )
+            .unwrap();
+            assert_eq!(data["alice"].draws, 1);
+            change.white_after - change.white_before
+        };
+
+        assert_eq!(draw(1050., 1000.), 0.);
+        assert!(draw(1051., 1000.) < 0.);
+    }
+
+    #[test]
+    fn games_order_puts_most_active_first() {
+        let mut data = HashMap::new();
+        for (player_id, games) in [("alice", 3), ("Bob", 7), ("carol", 7), ("dave", 0)] {
+            let mut player = PlayerRecord::new(INITIAL_RATING);
+            player.games = games;
+            data.insert(player_id.to_string(), player);
+        }
+
+        let order: Vec<&str> = sort_standings(&data, SortOrder::Games)
+            .iter()
+            .map(|(player_id, _)| player_id.as_str())
+            .collect();
+        assert_eq!(order, ["Bob", "carol", "alice", "dave"]);
+    }
+
+    #[test]
+    fn simulation_is_reproducible_and_converges() {
+        let first = simulate(10, 2000, 42);
+        let second = simulate(10, 2000, 42);
+        assert_eq!(first.len(), 10);
+        assert_eq!(
+            first.iter().map(|c| c.rms_error).collect::<Vec<f64>>(),
+            second.iter().map(|c| c.rms_error).collect::<Vec<f64>>()
+        );
+        assert_eq!(first.last().unwrap().games, 2000);
+        assert!(first.last().unwrap().correlation > 0.8);
+    }
+
+    #[test]
+    fn weight_multiplies_rating_change() {
+        let white_change = |weight| {
+            let mut data = HashMap::new();
+            data.insert("alice".to_string(), PlayerRecord::new(1000.));
+            data.insert("bob".to_string(), PlayerRecord::new(1000.));
+            let options = RatingOptions {
+                weight,
+                ..RatingOptions::default()
+            };
+            let change = update_ratings(
+                "alice".into(),
+                "bob".into(),
+                MatchResult::WhiteWin,
+                &options,
+                &mut data,
+            )
+            .unwrap();
+            change.white_after - change.white_before
+        };
+
+        assert!((white_change(Some(2.)) - 2. * white_change(None)).abs() < 1e-9);
+        for weight in ["0", "-1", "inf", "x"] {
+            assert!(Config::new(&args(&[
+                "leelo", "game", "a", "b", "1-0", "--weight", weight, "t.csv"
+            ]))
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn headerless_tables_keep_their_first_row() {
+        let path = temp_table("no-header", "alice,1100\nbob,900\n");
+        let path_str = path.to_str().unwrap();
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data.len(), 1);
+
+        let mut data = HashMap::new();
+        let store = StoreOptions {
+            no_header: true,
+            ..StoreOptions::default()
+        };
+        open_store(path_str, &store).read(&mut data).unwrap();
+        assert_eq!(data["alice"].rating(), 1100.);
+        assert_eq!(data["bob"].rating(), 900.);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn headerless_tables_are_written_without_a_header() {
+        let path = temp_table("no-header-write", "alice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo",
+            "game",
+            "alice",
+            "bob",
+            "1-0",
+            path_str,
+            "--no-header",
+        ]))
+        .unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("Player ID"));
+
+        // Both players survive another round trip, so neither row was taken for a header.
+        run(config(&[
+            "leelo",
+            "game",
+            "bob",
+            "alice",
+            "1/2-1/2",
+            path_str,
+            "--no-header",
+        ]))
+        .unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, false, b',', &mut data).unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data["alice"].games, 2);
+        assert_eq!(data["bob"].games, 2);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn adjustments_are_logged_replayed_and_undone() {
+        let path = temp_table("adjust", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "adjust", "alice", "-25", path_str])).unwrap();
+        assert!(!Path::new(&history_filename(path_str)).exists());
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        run(config(&["leelo", "adjust", "bob", "+10.5", path_str])).unwrap();
+        assert!(run(config(&["leelo", "adjust", "carol", "5", path_str])).is_err());
+
+        let mut adjusted = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut adjusted).unwrap();
+        assert_eq!(read_history(path_str).unwrap().len(), 1);
+        assert_eq!(read_history_records(path_str).unwrap().len(), 2);
+
+        // Rebuilding starts from the ratings stored before each player's first record, so the
+        // adjustment made before there was a history survives along with the logged one.
+        run(config(&["leelo", "rebuild", path_str])).unwrap();
+        let mut rebuilt = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut rebuilt).unwrap();
+        let total =
+            |data: &HashMap<String, PlayerRecord>| data.values().map(|p| p.rating()).sum::<f64>();
+        assert!((total(&adjusted) - 1985.5).abs() < 1e-9);
+        assert!((total(&rebuilt) - 1985.5).abs() < 1e-9);
+
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        let mut undone = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut undone).unwrap();
+        assert_eq!(undone["bob"].rating(), rebuilt["bob"].rating() - 10.5);
+        assert_eq!(read_history_records(path_str).unwrap().len(), 1);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn text_standings_pad_columns_to_the_longest_id() {
+        let mut data = HashMap::new();
+        data.insert("al".to_string(), PlayerRecord::new(1000.));
+        data.insert("a-very-long-player-id".to_string(), PlayerRecord::new(950.));
+        let mut veteran = PlayerRecord::new(1100.);
+        veteran.games = UNCERTAIN_GAMES;
+        data.insert("bo".to_string(), veteran);
+        let options = ViewOptions {
+            rank: true,
+            combined: true,
+            ..ViewOptions::default()
+        };
+
+        let mut out = Vec::new();
+        write_standings_text(
+            &sort_standings(&data, SortOrder::RatingDesc),
+            &options,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1 bo                      1100  1100  1100\n\
+             2 al?                     1000  1000  1000\n\
+             3 a-very-long-player-id?   950   950   950\n"
+        );
+    }
+
+    #[test]
+    fn history_since_keeps_games_on_or_after_the_date() {
+        let mut first = game("alice", "bob", MatchResult::WhiteWin);
+        first.timestamp = "2022-07-04T23:59:59Z".to_string();
+        let mut second = game("alice", "bob", MatchResult::Draw);
+        second.timestamp = "2022-07-05T00:00:00Z".to_string();
+        let since = parse_timestamp("2022-07-05").unwrap();
+        assert!(!recorded_since(&HistoryRecord::Game(first), since));
+        assert!(recorded_since(&HistoryRecord::Game(second), since));
+
+        let err = Config::new(&args(&["leelo", "history", "--since", "July", "t.csv"]))
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("invalid date 'July' for --since."));
+    }
+
+    #[test]
+    fn file_flag_stands_in_for_the_trailing_filename() {
+        let parsed = Config::new(&args(&[
+            "leelo", "--file", "t.csv", "game", "a", "b", "1-0",
+        ]))
+        .ok()
+        .unwrap();
+        assert_eq!(parsed.filename.as_deref(), Some("t.csv"));
+        let parsed = Config::new(&args(&["leelo", "view", "--file", "t.csv", "t.csv"]))
+            .ok()
+            .unwrap();
+        assert_eq!(parsed.filename.as_deref(), Some("t.csv"));
+
+        let err = Config::new(&args(&["leelo", "view", "--file", "a.csv", "b.csv"]))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "--file a.csv and filename b.csv disagree for `view`."
+        );
+    }
+
+    #[test]
+    fn max_change_caps_each_players_change() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1000.));
+        let mut veteran = PlayerRecord::new(1000.);
+        veteran.games = PROVISIONAL_GAMES;
+        data.insert("bob".to_string(), veteran);
+        let options = RatingOptions {
+            max_change: Some(30.),
+            ..RatingOptions::default()
+        };
+
+        // Alice is provisional, so only her change reaches the cap.
+        let change = update_ratings(
+            "alice".into(),
+            "bob".into(),
+            MatchResult::WhiteWin,
+            &options,
+            &mut data,
+        )
+        .unwrap();
+        assert_eq!(change.white_after - change.white_before, 30.);
+        assert!((change.black_after - change.black_before + K / 2.).abs() < 1e-9);
+        assert!(Config::new(&args(&[
+            "leelo",
+            "game",
+            "a",
+            "b",
+            "1-0",
+            "--max-change",
+            "-1",
+            "t.csv"
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn peak_is_the_highest_stored_rating() {
+        let path = temp_table("peak", "Player ID,Rating\n");
+        let path_str = path.to_str().unwrap();
+        run(config(&[
+            "leelo",
+            "player",
+            "alice",
+            "--initial",
+            "1200",
+            path_str,
+        ]))
+        .unwrap();
+        run(config(&["leelo", "player", "bob", path_str])).unwrap();
+        let mut peaks = Vec::new();
+        for (white, black, result) in [
+            ("alice", "bob", "1-0"),
+            ("bob", "alice", "0-1"),
+            ("alice", "bob", "0-1"),
+        ] {
+            run(config(&[
+                "leelo", "game", white, black, result, "--k", "10", path_str,
+            ]))
+            .unwrap();
+            let mut data = HashMap::new();
+            read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+            peaks.push(data["alice"].rating());
+        }
+
+        // The peak is after the second game, at the rating the --k 10 games actually gave.
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        let history = read_history_records(path_str).unwrap();
+        let (rating, timestamp) = peak_rating("alice", &history, &data).unwrap();
+        assert!(peaks[1] > peaks[0] && peaks[1] > peaks[2]);
+        assert_eq!(rating, peaks[1]);
+        assert_eq!(timestamp, record_timestamp(&history[1]));
+        assert!(peak_rating("carol", &history, &data).is_none());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn ffa_rates_each_player_against_the_whole_field() {
+        let mut data = HashMap::new();
+        for player_id in ["alice", "bob", "carol"] {
+            data.insert(player_id.to_string(), PlayerRecord::new(1000.));
+        }
+        let ranking: Vec<String> = ["alice", "bob", "carol"].map(String::from).to_vec();
+        let changes = update_ffa_ratings(&ranking, &RatingOptions::default(), &mut data).unwrap();
+
+        // Between evenly matched players, the winner gains half of K and the middle is even.
+        assert_eq!(changes[0].after - changes[0].before, PROVISIONAL_K / 2.);
+        assert_eq!(changes[1].after, changes[1].before);
+        assert_eq!(data["carol"].rating(), 1000. - PROVISIONAL_K / 2.);
+        assert_eq!(
+            (data["alice"].wins, data["bob"].draws, data["carol"].losses),
+            (1, 1, 1)
+        );
+
+        let parsed = Config::new(&args(&["leelo", "ffa", "a", "b", "c", "t.csv"]))
+            .ok()
+            .unwrap();
+        assert_eq!(parsed.filename.as_deref(), Some("t.csv"));
+        assert!(
+            matches!(parsed.operation, Operation::FreeForAll { ranking, .. } if ranking.len() == 3)
+        );
+        let ranking = vec!["alice".to_string(), "alice".to_string()];
+        assert!(update_ffa_ratings(&ranking, &RatingOptions::default(), &mut data).is_err());
+    }
+
+    #[test]
+    fn table_version_is_checked_and_upgraded() {
+        let path = temp_table("version", "Player ID,Rating,Games\nalice,1000,3\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "rename", "alice", "bob", path_str])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["bob"].games, 3);
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains(",RD,Version,Last Played\n"));
+
+        fs::write(&path, "Player ID,Rating,Games,Wins,Losses,Draws,White Rating,Black Rating,RD,Version\nbob,1000,0,0,0,0,1000,1000,350,4\n").unwrap();
+        let err = read_to_hashmap(path_str, true, b',', &mut HashMap::new()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("table uses version 4 of the format"));
+        remove_table(&path);
+    }
+
+    #[test]
+    fn color_highlights_the_top_three() {
+        let mut data = HashMap::new();
+        for (player_id, rating) in [("a", 1300.), ("b", 1200.), ("c", 1100.), ("d", 1000.)] {
+            data.insert(player_id.to_string(), PlayerRecord::new(rating));
+        }
+        let options = ViewOptions {
+            color: true,
+            ..ViewOptions::default()
+        };
+
+        let mut out = Vec::new();
+        write_standings_text(
+            &sort_standings(&data, SortOrder::RatingDesc),
+            &options,
+            &mut out,
+        )
+        .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "\x1b[1;33ma?  1300  1300\x1b[0m");
+        assert!(lines[2].starts_with("\x1b[33m"));
+        assert_eq!(lines[3], "d?  1000  1000");
+    }
+
+    #[test]
+    fn expected_round_robin_sums_expected_scores() {
+        let players = [("bob", 1000.), ("alice", 1200.), ("carol", 1000.)];
+        let standings = expected_round_robin(&players, RATING_CONST);
+
+        assert_eq!(
+            standings.iter().map(|(id, _)| *id).collect::<Vec<&str>>(),
+            ["alice", "bob", "carol"]
+        );
+        // Every game hands out one point in total.
+        let total: f64 = standings.iter().map(|(_, points)| points).sum();
+        assert!((total - 3.).abs() < 1e-9);
+        assert_eq!(standings[1].1, standings[2].1);
+    }
+
+    #[test]
+    fn negative_ratings_display_signed() {
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(-150.4));
+        let mut out = Vec::new();
+        write_standings_markdown(&sort_standings(&data, SortOrder::RatingDesc), &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("| alice | -150 |\n"));
+
+        let mut out = Vec::new();
+        let standings = sort_standings(&data, SortOrder::RatingDesc);
+        write_standings_text(&standings, &ViewOptions::default(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "alice?  -150  -150\n");
+    }
+
+    #[test]
+    fn batch_games_apply_in_order_and_skip_failures() {
+        let path = temp_table("batch", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        let err = run(config(&[
+            "leelo",
+            "game",
+            path_str,
+            "--batch",
+            "alice bob 1-0",
+            "alice zed 1-0",
+            "bob alice",
+            "bob alice draw",
+        ]))
+        .unwrap_err();
+        assert_eq!(err.to_string(), "2 of 4 games failed.");
+
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data["alice"].games, 2);
+        assert_eq!(read_history(path_str).unwrap().len(), 2);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn whatif_requires_both_k_values() {
+        let parsed = Config::new(&args(&[
+            "leelo", "whatif", "a", "b", "1-0", "t.csv", "--k1", "40", "--k2", "20",
+        ]))
+        .ok()
+        .unwrap();
+        assert!(matches!(
+            parsed.operation,
+            Operation::WhatIf { k_values, .. } if k_values == [40., 20.]
+        ));
+
+        let err = Config::new(&args(&[
+            "leelo", "whatif", "a", "b", "1-0", "t.csv", "--k1", "40",
+        ]))
+        .err()
+        .unwrap();
+        assert_eq!(err.to_string(), "missing --k2 for `whatif`.");
+    }
+
+    #[test]
+    fn top_shows_the_highest_rated_players() {
+        let path = temp_table(
+            "top-command",
+            "Player ID,Rating\nalice,1000\nbob,1100\ncarol,900\n",
+        );
+        let path_str = path.to_str().unwrap();
+
+        run(config(&["leelo", "top", "2", path_str])).unwrap();
+        run(config(&["leelo", "top", "10", path_str, "--format", "csv"])).unwrap();
+        let top: Vec<String> = open_store(path_str, &StoreOptions::default())
+            .top(2)
+            .unwrap()
+            .into_iter()
+            .map(|(player_id, _)| player_id)
+            .collect();
+        assert_eq!(top, ["bob", "alice"]);
+        for n in ["0", "-1", "two"] {
+            let err = Config::new(&args(&["leelo", "top", n, path_str]))
+                .err()
+                .unwrap();
+            assert_eq!(
+                err.to_string(),
+                "number of players must be a positive integer."
+            );
+        }
+        remove_table(&path);
+        assert!(run(config(&["leelo", "top", "2", path_str])).is_err());
+    }
+
+    #[test]
+    fn streamed_top_matches_the_sorted_table() {
+        let path = temp_table(
+            "top",
+            "Player ID,Rating\nalice,1000\nbob,nan\ncarol,1200\ndave,900\nerin,1200\n",
+        );
+        let path_str = path.to_str().unwrap();
+        let store = open_store(path_str, &StoreOptions::default());
+
+        let ids = |n| -> Vec<String> {
+            store
+                .top(n)
+                .unwrap()
+                .into_iter()
+                .map(|(player_id, _)| player_id)
+                .collect()
+        };
+        assert_eq!(ids(3), ["carol", "erin", "alice"]);
+        assert_eq!(ids(10), ["carol", "erin", "alice", "dave", "bob"]);
+        assert!(ids(0).is_empty());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn verify_reports_every_problem() {
+        let path = temp_table(
+            "verify",
+            "Player ID,Rating,Games,Wins,Losses,Draws\n\
+             alice,1000,2,1,1,0\n\
+             bob,abc\n\
+             carol,inf\n\
+             alice,900\n\
+             dave,1000,3,1,0,0\n",
+        );
+        let path_str = path.to_str().unwrap();
+
+        let problems = open_store(path_str, &StoreOptions::default())
+            .verify()
+            .unwrap();
+        assert_eq!(problems.len(), 5);
+        assert!(problems[0].starts_with("line 3: "));
+        assert_eq!(
+            problems[1],
+            "line 4: White rating of carol is not a finite number."
+        );
+        assert_eq!(
+            problems[3],
+            "line 5: duplicate player id alice, first seen on line 2."
+        );
+        assert_eq!(
+            problems[4],
+            "line 6: dave has 3 games but a record of +1 -0 =0."
+        );
+        assert!(run(config(&["leelo", "verify", path_str])).is_err());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn games_set_last_played_and_undo_restores_it() {
+        let path = temp_table(
+            "last-played",
+            "Player ID,Rating\nalice,1000\nbob,1000\ncarol,1000\n",
+        );
+        let path_str = path.to_str().unwrap();
+        let last_played = |player_id: &str| {
+            let mut data = HashMap::new();
+            read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+            data.remove(player_id).unwrap().last_played
+        };
+
+        assert_eq!(last_played("alice"), None);
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+        let first = last_played("alice").unwrap();
+        assert!(parse_timestamp(&first).is_some());
+        run(config(&[
+            "leelo", "game", "carol", "alice", "1-0", path_str,
+        ]))
+        .unwrap();
+        run(config(&["leelo", "undo", path_str])).unwrap();
+        assert_eq!(last_played("alice"), Some(first));
+        assert_eq!(last_played("carol"), None);
+        remove_table(&path);
+    }
+
+    #[test]
+    fn missing_players_get_a_suggestion() {
+        assert_eq!(edit_distance("alice", "alcie"), 2);
+        assert_eq!(edit_distance("", "bob"), 3);
+        let mut data = HashMap::new();
+        for player_id in ["alice", "alicia", "bob"] {
+            data.insert(player_id.to_string(), PlayerRecord::new(1000.));
+        }
+
+        let err = update_ratings(
+            "alcie".into(),
+            "bob".into(),
+            MatchResult::WhiteWin,
+            &RatingOptions::default(),
+            &mut data,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "white player alcie not found. Did you mean 'alice'?"
+        );
+        assert_eq!(
+            get_rating("zed", &data).unwrap_err().to_string(),
+            "player zed not found."
+        );
+    }
+
+    #[test]
+    fn prune_removes_players_below_the_threshold() {
+        let path = temp_table(
+            "prune",
+            "Player ID,Rating\nalice,1000\nbob,899.5\ncarol,900\ndave,-20\n",
+        );
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo",
+            "prune",
+            "--below",
+            "900",
+            "--dry-run",
+            path_str,
+        ]))
+        .unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data.len(), 4);
+
+        assert_eq!(prune_players(900., &mut data), ["bob", "dave"]);
+        run(config(&["leelo", "prune", "--below", "900", path_str])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert!(data.contains_key("carol") && !data.contains_key("bob"));
+        assert!(Config::new(&args(&["leelo", "prune", path_str])).is_err());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn upset_bonus_scales_k_with_the_rating_gap() {
+        let white_change = |black_rating, upset_bonus| {
+            let mut data = HashMap::new();
+            data.insert("alice".to_string(), PlayerRecord::new(1000.));
+            data.insert("bob".to_string(), PlayerRecord::new(black_rating));
+            let options = RatingOptions {
+                k: Some(40.),
+                upset_bonus,
+                ..RatingOptions::default()
+            };
+            let change = update_ratings(
+                "alice".into(),
+                "bob".into(),
+                MatchResult::WhiteWin,
+                &options,
+                &mut data,
+            )
+            .unwrap();
+            change.white_after - change.white_before
+        };
+
+        assert!((white_change(1200., true) - 1.5 * white_change(1200., false)).abs() < 1e-9);
+        // The bonus stops growing once the gap reaches UPSET_GAP.
+        assert!((white_change(1600., true) - 2. * white_change(1600., false)).abs() < 1e-9);
+        // A win by the favorite is rated as usual.
+        assert_eq!(white_change(800., true), white_change(800., false));
+        assert!(Config::new(&args(&[
+            "leelo",
+            "game",
+            "alice",
+            "bob",
+            "1-0",
+            "table.csv",
+            "--system",
+            "glicko",
+            "--upset-bonus",
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn gzipped_tables_round_trip() {
+        let path = env::temp_dir().join(format!("leelo-test-{}-gzip.csv.gz", std::process::id()));
+        let path_str = path.to_str().unwrap();
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1012.5));
+        write_to_csv(path_str, true, b',', &data).unwrap();
+
+        // The file on disk is gzip, not plain CSV.
+        assert_eq!(fs::read(&path).unwrap()[..2], [0x1f, 0x8b]);
+        let mut read_back = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut read_back).unwrap();
+        assert_eq!(read_back["alice"].rating(), 1012.5);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn history_page_counts_back_from_the_most_recent() {
+        let records: Vec<HistoryRecord> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|id| HistoryRecord::Game(game(id, "z", MatchResult::WhiteWin)))
+            .collect();
+        let page = |limit, offset| -> Vec<String> {
+            history_page(records.clone(), limit, offset)
+                .into_iter()
+                .map(|record| match record {
+                    HistoryRecord::Game(entry) => entry.white_player_id,
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        assert_eq!(page(Some(2), 0), ["d", "e"]);
+        assert_eq!(page(Some(2), 2), ["b", "c"]);
+        assert_eq!(page(Some(2), 4), ["a"]);
+        assert!(page(Some(2), 5).is_empty());
+        assert_eq!(page(None, 3), ["a", "b"]);
+        assert_eq!(page(None, 0).len(), 5);
+    }
+
+    #[test]
+    fn repl_applies_commands_and_saves_on_request() {
+        let path = temp_table("repl", "Player ID,Rating\nalice,1000\n");
+        let path_str = path.to_str().unwrap();
+        let store = open_store(path_str, &StoreOptions::default());
+        let input =
+            "player bob\n\ngame alice bob 1-0\ngame alice nobody 1-0\nsave\nquit\nplayer carol\n";
+
+        run_repl(
+            input.as_bytes(),
+            path_str,
+            store.as_ref(),
+            false,
+            false,
+            true,
+        )
+        .unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data.len(), 2);
+        assert!(data["alice"].rating() > 1000.);
+        assert_eq!(read_history(path_str).unwrap().len(), 1);
+
+        // With autosave each change is written straight away, even without a quit.
+        run_repl(
+            "player carol\n".as_bytes(),
+            path_str,
+            store.as_ref(),
+            true,
+            false,
+            true,
+        )
+        .unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert!(data.contains_key("carol"));
+        assert!(repl_command(args(&["undo"]), path_str, &mut data).is_err());
+        let _ = fs::remove_file(history_filename(path_str));
+        remove_table(&path);
+    }
+
+    #[test]
+    fn rating_for_rank_matches_the_player_to_beat() {
+        let mut data = HashMap::new();
+        for (player_id, rating) in [("a", 1300.), ("b", 1200.), ("c", 1200.), ("d", 900.)] {
+            data.insert(player_id.to_string(), PlayerRecord::new(rating));
+        }
+
+        assert_eq!(rating_for_rank("d", 1, &data), Some(1300.));
+        // Tying b and c is enough to share second place.
+        assert_eq!(rating_for_rank("d", 2, &data), Some(1200.));
+        assert_eq!(rating_for_rank("d", 3, &data), Some(1200.));
+        assert_eq!(rating_for_rank("a", 3, &data), Some(900.));
+        assert_eq!(rating_for_rank("d", 4, &data), None);
+        assert!(Config::new(&args(&["leelo", "target", "d", "0", "table.csv"])).is_err());
+    }
+
+    #[test]
+    fn html_standings_are_a_complete_escaped_page() {
+        let (alice, bob) = ("alice".to_string(), "<b>ob&".to_string());
+        let (alice_record, bob_record) = (PlayerRecord::new(1040.), PlayerRecord::new(960.));
+        let mut out = Vec::new();
+        write_standings_html(&[(&alice, &alice_record), (&bob, &bob_record)], &mut out).unwrap();
+        let page = String::from_utf8(out).unwrap();
+
+        assert!(page.starts_with("<!DOCTYPE html>\n"));
+        assert!(page.ends_with("</html>\n"));
+        assert!(page.contains("<tr><th>Rank</th><th>Player ID</th><th>Rating</th></tr>"));
+        assert!(page.contains("<tr><td>1</td><td>alice</td><td>1040</td></tr>"));
+        assert!(page.contains("<tr><td>2</td><td>&lt;b&gt;ob&amp;</td><td>960</td></tr>"));
+    }
+
+    #[test]
+    fn drift_tracks_the_mean_of_each_months_active_players() {
+        let dated = |white: &str, black: &str, ratings: (f64, f64), timestamp: &str| {
+            let mut entry = game(white, black, MatchResult::WhiteWin);
+            entry.timestamp = timestamp.to_string();
+            entry.white_rating = Some(ratings.0);
+            entry.black_rating = Some(ratings.1);
+            HistoryRecord::Game(entry)
+        };
+        // Alice was seeded at 1200 and the games were rated with --k 10.
+        let history = [
+            dated("alice", "bob", (1200., 1000.), "2022-07-05T10:00:00Z"),
+            dated("alice", "bob", (1202.5, 997.5), "2022-07-20T10:00:00Z"),
+            dated("carol", "alice", (1000., 1200.), "2022-08-01T10:00:00Z"),
+        ];
+        let mut data = HashMap::new();
+        for (player_id, white_rating, black_rating) in [
+            ("alice", 1204.5, 1197.),
+            ("bob", 1000., 995.5),
+            ("carol", 1003., 1000.),
+        ] {
+            let mut player = PlayerRecord::new(INITIAL_RATING);
+            player.white_rating = white_rating;
+            player.black_rating = black_rating;
+            data.insert(player_id.to_string(), player);
+        }
+
+        let drift = rating_drift(&history, &data);
+        assert_eq!(drift.len(), 2);
+        // At the end of July alice is at (1204.5 + 1200) / 2 and bob at (1000 + 995.5) / 2.
+        assert_eq!((drift[0].month.as_str(), drift[0].players), ("2022-07", 2));
+        assert!((drift[0].mean - 1100.).abs() < 1e-9);
+        // Bob sat August out, so only alice and carol count.
+        let mean = (data["alice"].rating() + data["carol"].rating()) / 2.;
+        assert_eq!((drift[1].month.as_str(), drift[1].players), ("2022-08", 2));
+        assert!((drift[1].mean - mean).abs() < 1e-9);
+        assert!(rating_drift(&[], &data).is_empty());
+    }
+
+    #[test]
+    fn drift_uses_the_ratings_games_were_played_at() {
+        let path = temp_table("drift", "Player ID,Rating\nalice,1000\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+        run(config(&[
+            "leelo", "game", "alice", "bob", "1-0", "--k", "10", path_str,
+        ]))
+        .unwrap();
+        run(config(&["leelo", "adjust", "bob", "+300", path_str])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+
+        let drift = rating_drift(&read_history_records(path_str).unwrap(), &data);
+        assert_eq!(drift.len(), 1);
+        assert!((drift[0].mean - 1150.).abs() < 1e-9);
+        run(config(&["leelo", "drift", path_str])).unwrap();
+        remove_table(&path);
+    }
+
+    #[test]
+    fn table_storage_runs_in_memory() {
+        // Only the table is kept in memory. The lock file still goes next to it, so it's named
+        // inside the temp directory.
+        let table = env::temp_dir().join(format!("leelo-test-{}-memory.csv", std::process::id()));
+        let name = table.to_str().unwrap();
+        let run_memory = |command: &[&str]| {
+            let mut command = command.to_vec();
+            command.extend(["--backend", "memory", name]);
+            run(config(&command))
+        };
+
+        assert!(run_memory(&["leelo", "view"]).is_err());
+        run_memory(&["leelo", "new"]).unwrap();
+        run_memory(&["leelo", "player", "alice"]).unwrap();
+        run_memory(&["leelo", "player", "bob", "--rating", "1500"]).unwrap();
+        run_memory(&["leelo", "adjust", "alice", "20"]).unwrap();
+        run_memory(&["leelo", "prune", "--below", "1100"]).unwrap();
+        run_memory(&["leelo", "view"]).unwrap();
+
+        let mut data = HashMap::new();
+        open_store(
+            name,
+            &StoreOptions {
+                backend: Some(Backend::Memory),
+                ..StoreOptions::default()
+            },
+        )
+        .read(&mut data)
+        .unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data["bob"].rating(), 1500.);
+        assert!(!table.exists());
+        // None of these commands log to the history, and each lock is gone once it's released.
+        assert!(!Path::new(&history_filename(name)).exists());
+        assert!(!Path::new(&format!("{}.lock", name)).exists());
+    }
+
+    #[test]
+    fn dedup_skips_repeated_rows_only() {
+        let rows = "alice,bob,1-0,2022-07-05T10:00:00Z\n\
+                    alice,bob,1-0,2022-07-05T10:00:00Z\n\
+                    alice,bob,1-0,2022-07-06T10:00:00Z\n\
+                    alice,bob,0-1,2022-07-05T10:00:00Z\n\
+                    carol,dave,1/2-1/2\n\
+                    carol,dave,0.5-0.5\n";
+        let rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(rows.as_bytes());
+        let (games, duplicates) = dedup_games(parse_games(rdr).unwrap());
+
+        assert_eq!(duplicates, 2);
+        let lines: Vec<u64> = games.iter().map(|game| game.line).collect();
+        assert_eq!(lines, [1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn classes_follow_the_uscf_thresholds() {
+        assert_eq!(rating_class(2400.), "Senior Master");
+        assert_eq!(rating_class(1999.9), "A");
+        assert_eq!(rating_class(1000.), "E");
+        assert_eq!(rating_class(-50.), "J");
+        assert_eq!(rating_class(f64::NAN), "-");
+
+        let mut data = HashMap::new();
+        data.insert("alice".to_string(), PlayerRecord::new(1850.));
+        data.insert("bob".to_string(), PlayerRecord::new(990.));
+        let options = ViewOptions {
+            classes: true,
+            ..ViewOptions::default()
+        };
+        let mut out = Vec::new();
+        write_standings_text(
+            &sort_standings(&data, SortOrder::RatingDesc),
+            &options,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "alice?  1850  1850  A\nbob?     990   990  F\n"
+        );
+    }
+
+    #[test]
+    fn match_odds_follow_the_binomial_model() {
+        let close = |a: (f64, f64, f64), b: (f64, f64, f64)| {
+            (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9 && (a.2 - b.2).abs() < 1e-9
+        };
+        // Best of three at 0.6 a game: win two or three.
+        let a_wins = 0.6 * 0.6 * 0.6 + 3. * 0.6 * 0.6 * 0.4;
+        assert!(close(match_odds(0.6, 0., 3), (a_wins, 0., 1. - a_wins)));
+        // With every game drawn, the match is always tied.
+        assert!(close(match_odds(0.5, 1., 4), (0., 1., 0.)));
+        // One game with draws: a draw rate of 0.3 at 0.6 leaves a 0.45 chance to win.
+        assert!(close(match_odds(0.6, 0.3, 1), (0.45, 0.3, 0.25)));
+        // The draw rate is capped at what the underdog's expected score allows.
+        assert!(close(match_odds(0.9, 0.5, 1), (0.8, 0.2, 0.)));
+    }
+
+    #[test]
+    fn delimiter_applies_to_reading_and_writing_tables() {
+        let path = temp_table("delimiter", "Player ID;Rating\nalice;1012.5\n");
+        let path_str = path.to_str().unwrap();
+
+        run(config(&[
+            "leelo",
+            "player",
+            "bob",
+            path_str,
+            "--delimiter",
+            ";",
+        ]))
+        .unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b';', &mut data).unwrap();
+        assert_eq!(data["alice"].rating(), 1012.5);
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("Player ID;Rating;"));
+        // Read as commas, the semicolon-separated header is a single column.
+        assert!(run(config(&["leelo", "view", path_str])).is_err());
+        for bad in ["", ";;", "\"", "é"] {
+            assert!(Config::new(&args(&["leelo", "view", path_str, "--delimiter", bad])).is_err());
+        }
+        remove_table(&path);
+    }
+
+    #[test]
+    fn new_refuses_to_overwrite_without_force() {
+        let path = temp_table("new-existing", "Player ID,Rating\nalice,1000\n");
+        let path_str = path.to_str().unwrap();
+
+        assert!(run(config(&["leelo", "new", path_str])).is_err());
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert_eq!(data.len(), 1);
+
+        run(config(&["leelo", "new", path_str, "--force"])).unwrap();
+        let mut data = HashMap::new();
+        read_to_hashmap(path_str, true, b',', &mut data).unwrap();
+        assert!(data.is_empty());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn calibration_buckets_logged_expected_scores() {
+        let logged = |expected, result| HistoryEntry {
+            expected_score: Some(expected),
+            ..game("alice", "bob", result)
+        };
+        let history = [
+            logged(0.52, MatchResult::WhiteWin),
+            logged(0.58, MatchResult::Draw),
+            logged(0.91, MatchResult::BlackWin),
+            logged(1., MatchResult::WhiteWin),
+            game("alice", "bob", MatchResult::WhiteWin),
+        ];
+
+        let buckets = calibration(&history);
+        assert_eq!(buckets.len(), 2);
+        assert_eq!((buckets[0].low, buckets[0].games), (0.5, 2));
+        assert!((buckets[0].expected - 0.55).abs() < 1e-9);
+        assert!((buckets[0].actual - 0.75).abs() < 1e-9);
+        assert_eq!((buckets[1].high, buckets[1].games), (1., 2));
+        assert!((buckets[1].actual - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn games_log_their_expected_score() {
+        let path = temp_table("expected-log", "Player ID,Rating\nalice,1200\nbob,1000\n");
+        let path_str = path.to_str().unwrap();
+        run(config(&["leelo", "game", "alice", "bob", "1-0", path_str])).unwrap();
+
+        let history = read_history(path_str).unwrap();
+        let expected = history[0].expected_score.unwrap();
+        assert!((expected - expected_score(1200., 1000.)).abs() < 1e-12);
+        let _ = fs::remove_file(history_filename(path_str));
+        remove_table(&path);
+    }
+
+    #[test]
+    fn forecast_prints_the_change_for_each_result() {
+        // 200 points below the opponent, so the expected score is a quarter.
+        let forecast =
+            |games, result| format_forecast("alice", 1000., games, 1200., result, RATING_CONST);
+        let established = PROVISIONAL_GAMES;
+        assert_eq!(
+            forecast(established, MatchResult::WhiteWin),
+            "alice\t1000 -> 1030 (+30)"
+        );
+        assert_eq!(
+            forecast(established, MatchResult::Draw),
+            "alice\t1000 -> 1010 (+10)"
+        );
+        assert_eq!(
+            forecast(established, MatchResult::BlackWin),
+            "alice\t1000 -> 990 (-10)"
+        );
+        // A provisional player's K is doubled.
+        assert_eq!(
+            forecast(0, MatchResult::WhiteWin),
+            "alice\t1000 -> 1060 (+60)"
+        );
+
+        let path = temp_table("forecast", "Player ID,Rating\nalice,1000\nbob,1200\n");
+        let path_str = path.to_str().unwrap();
+        run(config(&[
+            "leelo", "forecast", "alice", "bob", "draw", path_str,
+        ]))
+        .unwrap();
+        assert!(run(config(&[
+            "leelo", "forecast", "alice", "bobb", "1-0", path_str
+        ]))
+        .is_err());
+        remove_table(&path);
+    }
+
+    #[test]
+    fn show_prints_every_field_of_a_player() {
+        let mut player = PlayerRecord::new(1000.);
+        player.white_rating = 1012.4;
+        player.black_rating = 990.;
+        player.rd = 120.6;
+        player.games = 5;
+        player.wins = 3;
+        player.losses = 1;
+        player.draws = 1;
+        player.last_played = Some("2022-07-05T10:00:00Z".to_string());
+        let details = |player: &PlayerRecord, precision| {
+            let mut out = Vec::new();
+            write_player_details("alice", player, precision, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            details(&player, 0),
+            "Player ID\talice\nRating\t1001\nWhite Rating\t1012\nBlack Rating\t990\n\
+             RD\t121\nGames\t5\nWins\t3\nLosses\t1\nDraws\t1\n\
+             Last Played\t2022-07-05T10:00:00Z\n"
+        );
+        assert!(details(&player, 1).contains("\nWhite Rating\t1012.4\n"));
+        assert!(details(&PlayerRecord::new(1000.), 0).ends_with("\nLast Played\t-\n"));
+
+        let path = temp_table("show", "Player ID,Rating\nalice,1000\n");
+        let path_str = path.to_str().unwrap();
+        run(config(&["leelo", "show", "alice", path_str])).unwrap();
+        let err = run(config(&["leelo", "show", "alcie", path_str])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "player alcie not found. Did you mean 'alice'?"
+        );
+        remove_table(&path);
+    }
+}
@@ -7,13 +7,13 @@ fn main() {
     let args: Vec<String> = env::args().collect();
 
     let config = Config::new(&args).unwrap_or_else(|err| {
-        println!("Problem parsing arguments: {}", err);
-        process::exit(1);
+        eprintln!("Problem parsing arguments: {}", err);
+        process::exit(err.exit_code());
     });
 
     if let Err(e) = leelo::run(config) {
-        println!("Application Error: {}", e);
+        eprintln!("Application Error: {}", e);
 
-        process::exit(1);
+        process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,93 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn leelo(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_leelo"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn stdout_only_contains_command_output() {
+    let path = env::temp_dir().join(format!("leelo-cli-stdout-{}.csv", std::process::id()));
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, "Player ID,Rating\nalice,1000\nbob,1000\n").unwrap();
+
+    let game = leelo(&["game", "alice", "bob", "1-0", path_str]);
+    assert!(game.status.success());
+    assert!(game.stdout.is_empty());
+    assert!(!game.stderr.is_empty());
+
+    let error = leelo(&["game", "alice", "zed", "1-0", path_str]);
+    assert!(!error.status.success());
+    assert!(error.stdout.is_empty());
+    assert!(!error.stderr.is_empty());
+
+    let list = leelo(&["list", path_str]);
+    assert_eq!(String::from_utf8(list.stdout).unwrap(), "alice\nbob\n");
+
+    fs::remove_file(format!("{}.history.csv", path_str)).unwrap();
+    fs::remove_file(format!("{}.bak", path_str)).unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn filename_falls_back_to_leelo_file() {
+    let path = env::temp_dir().join(format!("leelo-cli-env-{}.csv", std::process::id()));
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, "Player ID,Rating\nalice,1000\n").unwrap();
+
+    let list = Command::new(env!("CARGO_BIN_EXE_leelo"))
+        .args(["list"])
+        .env("LEELO_FILE", path_str)
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8(list.stdout).unwrap(), "alice\n");
+
+    // An explicit filename takes precedence over the environment.
+    let list = Command::new(env!("CARGO_BIN_EXE_leelo"))
+        .args(["list", "leelo-does-not-exist.csv"])
+        .env("LEELO_FILE", path_str)
+        .output()
+        .unwrap();
+    assert!(!list.status.success());
+
+    let list = Command::new(env!("CARGO_BIN_EXE_leelo"))
+        .args(["list"])
+        .env_remove("LEELO_FILE")
+        .output()
+        .unwrap();
+    assert_eq!(list.status.code(), Some(2));
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn import_reads_games_from_stdin() {
+    let path = env::temp_dir().join(format!("leelo-cli-stdin-{}.csv", std::process::id()));
+    let path_str = path.to_str().unwrap();
+    fs::write(&path, "Player ID,Rating\nalice,1000\nbob,1000\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_leelo"))
+        .args(["import", "-", path_str, "--no-backup"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"alice,bob,1-0\nbob,alice,draw\n")
+        .unwrap();
+    assert!(child.wait().unwrap().success());
+
+    let history = fs::read_to_string(format!("{}.history.csv", path_str)).unwrap();
+    assert_eq!(history.lines().count(), 3);
+
+    fs::remove_file(format!("{}.history.csv", path_str)).unwrap();
+    fs::remove_file(&path).unwrap();
+}